@@ -0,0 +1,86 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use libcobs::recv::{CobsReceiver, CobsReceiverOperation};
+use libcobs::send::{CobsSender, CobsSenderOperation};
+
+struct Sink {
+    data: Vec<u8>,
+}
+
+impl CobsSenderOperation for Sink {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.data.extend_from_slice(buf);
+        Some(buf.len())
+    }
+}
+
+struct Source<'l> {
+    data: &'l [u8],
+    offset: usize,
+}
+
+impl CobsReceiverOperation for Source<'_> {
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        let length = len.min(self.data.len() - self.offset);
+
+        if length == 0 {
+            return None;
+        }
+
+        self.offset += length;
+
+        Some(self.data[self.offset - length..self.offset].to_vec())
+    }
+}
+
+/// 1 MiB payload with a zero every `every` bytes (never when 0), encoded.
+fn frame(every: usize) -> Vec<u8> {
+    let payload: Vec<u8> = (0..1 << 20)
+        .map(|i: usize| if every > 0 && i % every == every - 1 { 0 } else { (i % 255) as u8 + 1 })
+        .collect();
+    let mut sink = Sink { data: Vec::new() };
+    {
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut sink));
+        let mut s: CobsSender = CobsSender::new(&sender);
+        s.send(&payload);
+    }
+
+    sink.data
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+
+    for every in [0, 64] {
+        let encoded = frame(every);
+        let mut out = vec![0u8; encoded.len()];
+        group.throughput(Throughput::Bytes(encoded.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("recv", every), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut source = Source { data: encoded, offset: 0 };
+                let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut source));
+                let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+                r.recv()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("decode", every), &encoded, |b, encoded| {
+            b.iter(|| libcobs::decode(encoded, &mut out))
+        });
+
+        group.bench_with_input(BenchmarkId::new("decode_trusted", every), &encoded, |b, encoded| {
+            b.iter(|| libcobs::decode_trusted(encoded, &mut out))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use libcobs::send::{CobsSender, CobsSenderOperation};
+
+struct Sink {
+    data: Vec<u8>,
+}
+
+impl CobsSenderOperation for Sink {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.data.extend_from_slice(buf);
+        Some(buf.len())
+    }
+}
+
+/// The byte-at-a-time loop `CobsSender::send` used before the zero scanner.
+fn scalar_send(sink: &mut Sink, buf: &[u8]) {
+    let mut i = 0;
+
+    loop {
+        let mut code: u8 = 0x01;
+        let start = i;
+
+        while i < buf.len() && buf[i] != 0 && code != 0xff {
+            code += 1;
+            i += 1;
+        }
+
+        sink.send(&[code]);
+        if code > 0x01 {
+            sink.send(&buf[start..i]);
+        }
+
+        if i >= buf.len() {
+            break;
+        }
+
+        if buf[i] == 0 && code < 0xff {
+            i += 1;
+        }
+    }
+
+    sink.send(&[0]);
+}
+
+/// 1 MiB payload with a zero every `every` bytes (never when 0).
+fn payload(every: usize) -> Vec<u8> {
+    (0..1 << 20)
+        .map(|i: usize| if every > 0 && i % every == every - 1 { 0 } else { (i % 255) as u8 + 1 })
+        .collect()
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+
+    for every in [0, 1000, 64] {
+        let buf = payload(every);
+        let mut sink = Sink {
+            data: Vec::with_capacity(buf.len() * 2),
+        };
+        group.throughput(Throughput::Bytes(buf.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("scalar", every), &buf, |b, buf| {
+            b.iter(|| {
+                sink.data.clear();
+                scalar_send(&mut sink, buf);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("send", every), &buf, |b, buf| {
+            b.iter(|| {
+                sink.data.clear();
+                let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut sink));
+                let mut s: CobsSender = CobsSender::new(&sender);
+                s.send(buf)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
@@ -0,0 +1,241 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Authenticated frames.
+//!
+//! Each frame carries `payload || counter || mac` before stuffing, where
+//! `counter` is a big-endian `u64` that must strictly increase from one frame
+//! to the next and `mac` is HMAC-SHA256 over `payload || counter`, truncated
+//! to the configured length.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::error::CobsError;
+use super::recv::CobsReceiver;
+use super::send::CobsSender;
+use super::statistics::CobsStatistics;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const COUNTER_LEN: usize = 8;
+pub const MAC_LEN_MAX: usize = 32;
+
+/// HMAC keyed with `key`, once `mac_len` is checked to be within `1..=MAC_LEN_MAX`.
+fn keyed(key: &[u8], mac_len: usize) -> Result<HmacSha256, CobsError> {
+    if mac_len == 0 || mac_len > MAC_LEN_MAX {
+        return Err(CobsError::Config);
+    }
+
+    // HMAC accepts keys of any length, this never fails.
+    HmacSha256::new_from_slice(key).map_err(|_| CobsError::Config)
+}
+
+pub struct AuthSender<'l> {
+    sender: CobsSender<'l>,
+    mac: HmacSha256,
+    mac_len: usize,
+    counter: u64,
+}
+
+impl<'l> AuthSender<'l> {
+    /// Fails with `CobsError::Config` unless `mac_len` is within `1..=MAC_LEN_MAX`.
+    pub fn new(sender: CobsSender<'l>, key: &[u8], mac_len: usize) -> Result<AuthSender<'l>, CobsError> {
+        Ok(AuthSender {
+            sender,
+            mac: keyed(key, mac_len)?,
+            mac_len,
+            counter: 1,
+        })
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.sender.stats()
+    }
+
+    /// Counter that will be carried by the next frame.
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Resume from a persisted counter, e.g. after a reboot.
+    pub fn set_counter(&mut self, counter: u64) {
+        self.counter = counter;
+    }
+
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize, CobsError> {
+        let next = self.counter.checked_add(1).ok_or(CobsError::Replay)?;
+        let mut frame: Vec<u8> = Vec::with_capacity(buf.len() + COUNTER_LEN + self.mac_len);
+
+        frame.extend_from_slice(buf);
+        frame.extend_from_slice(&self.counter.to_be_bytes());
+
+        let mut mac = self.mac.clone();
+        mac.update(&frame);
+        let tag = mac.finalize().into_bytes();
+        frame.extend_from_slice(&tag[..self.mac_len]);
+
+        let total = self.sender.send(&frame).ok_or(CobsError::Send)?;
+        self.counter = next;
+
+        Ok(total)
+    }
+}
+
+pub struct AuthReceiver<'l> {
+    receiver: CobsReceiver<'l>,
+    mac: HmacSha256,
+    mac_len: usize,
+    last: u64,
+}
+
+impl<'l> AuthReceiver<'l> {
+    /// Fails with `CobsError::Config` unless `mac_len` is within `1..=MAC_LEN_MAX`.
+    pub fn new(receiver: CobsReceiver<'l>, key: &[u8], mac_len: usize) -> Result<AuthReceiver<'l>, CobsError> {
+        Ok(AuthReceiver {
+            receiver,
+            mac: keyed(key, mac_len)?,
+            mac_len,
+            last: 0,
+        })
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.receiver.stats()
+    }
+
+    /// Counter of the last accepted frame.
+    pub fn counter(&self) -> u64 {
+        self.last
+    }
+
+    /// Restore the last accepted counter, e.g. after a reboot.
+    pub fn set_counter(&mut self, counter: u64) {
+        self.last = counter;
+    }
+
+    pub fn recv(&mut self) -> Result<Vec<u8>, CobsError> {
+        let mut data = self.receiver.recv().ok_or(CobsError::Recv)?;
+
+        if data.len() < COUNTER_LEN + self.mac_len {
+            return Err(CobsError::Auth);
+        }
+
+        let tag = data.len() - self.mac_len;
+        let mut mac = self.mac.clone();
+        mac.update(&data[..tag]);
+        mac.verify_truncated_left(&data[tag..])
+            .map_err(|_| CobsError::Auth)?;
+
+        let end = tag - COUNTER_LEN;
+        let mut counter = [0u8; COUNTER_LEN];
+        counter.copy_from_slice(&data[end..tag]);
+        let counter = u64::from_be_bytes(counter);
+        if counter <= self.last {
+            return Err(CobsError::Replay);
+        }
+
+        self.last = counter;
+        data.truncate(end);
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiverOperation;
+    use crate::testutil::{sent, Mem2Recv};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    const KEY: &[u8] = b"libcobs test key";
+
+    fn encode(frames: &[&[u8]], key: &[u8], mac_len: usize) -> Vec<u8> {
+        sent(|sender| {
+            let mut s = AuthSender::new(sender, key, mac_len).expect("mac_len");
+
+            for f in frames {
+                s.send(f).expect("send");
+            }
+            assert_eq!(s.counter(), frames.len() as u64 + 1);
+        })
+    }
+
+    #[test]
+    fn test_auth_roundtrip() {
+        let encoded = encode(&[&[0x11, 0x00, 0x22], &[]], KEY, 8);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = AuthReceiver::new(CobsReceiver::new(&receiver), KEY, 8).expect("mac_len");
+
+        assert_eq!(r.recv(), Ok(vec![0x11, 0x00, 0x22]));
+        assert_eq!(r.recv(), Ok(vec![]));
+        assert_eq!(r.counter(), 2);
+    }
+
+    #[test]
+    fn test_auth_full_mac() {
+        let encoded = encode(&[&[0x42; 300]], KEY, MAC_LEN_MAX);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = AuthReceiver::new(CobsReceiver::new(&receiver), KEY, MAC_LEN_MAX).expect("mac_len");
+
+        assert_eq!(r.recv(), Ok(vec![0x42; 300]));
+    }
+
+    #[test]
+    fn test_auth_wrong_key() {
+        let encoded = encode(&[&[0x11, 0x22]], b"another key", 8);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = AuthReceiver::new(CobsReceiver::new(&receiver), KEY, 8).expect("mac_len");
+
+        assert_eq!(r.recv(), Err(CobsError::Auth));
+    }
+
+    #[test]
+    fn test_auth_tampered() {
+        let mut encoded = encode(&[&[0x11, 0x22, 0x33]], KEY, 4);
+        encoded[1] ^= 0x01;
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = AuthReceiver::new(CobsReceiver::new(&receiver), KEY, 4).expect("mac_len");
+
+        assert_eq!(r.recv(), Err(CobsError::Auth));
+    }
+
+    #[test]
+    fn test_auth_replay() {
+        let frame = encode(&[&[0x11, 0x22]], KEY, 8);
+        let mut encoded = frame.clone();
+        encoded.extend_from_slice(&frame);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = AuthReceiver::new(CobsReceiver::new(&receiver), KEY, 8).expect("mac_len");
+
+        assert_eq!(r.recv(), Ok(vec![0x11, 0x22]));
+        assert_eq!(r.recv(), Err(CobsError::Replay));
+    }
+
+    #[test]
+    fn test_auth_mac_len() {
+        let mut m2r = Mem2Recv::new(&[]);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+
+        for mac_len in [0, MAC_LEN_MAX + 1] {
+            assert!(matches!(AuthReceiver::new(CobsReceiver::new(&receiver), KEY, mac_len), Err(CobsError::Config)));
+            sent(|sender| assert!(matches!(AuthSender::new(sender, KEY, mac_len), Err(CobsError::Config))));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::path::PathBuf;
+    use std::process::ExitCode;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    use libcobs::bridge::{dump, open_tty, Bridge, Direction, Pty};
+
+    const USAGE: &str = "usage: cobs-bridge [--tty PATH] [--ascii]
+
+Forwards bytes between side A and side B and prints every COBS frame seen
+in each direction. Side A is always a new pseudo-terminal; side B is another
+pseudo-terminal, or the terminal device given with --tty.
+
+options:
+  -t, --tty PATH   use this terminal device as side B
+  -a, --ascii      dump frames as escaped ASCII instead of hex
+  -h, --help       print this help";
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        STOP.store(true, Ordering::Relaxed);
+    }
+
+    /// Install `on_signal` for `signal`, without SA_RESTART so a blocked call returns.
+    fn catch(signal: libc::c_int) -> std::io::Result<()> {
+        // SAFETY: sigaction is plain old data, all zeroes is a valid value.
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+
+        // SAFETY: `sa_mask` is a valid sigset_t to write to; `action` is a valid,
+        // initialised sigaction whose handler only stores to an atomic, which is
+        // async-signal-safe; the old action pointer may be null.
+        let ret = unsafe {
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn main() -> ExitCode {
+        let mut tty: Option<PathBuf> = None;
+        let mut ascii = false;
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-t" | "--tty" => match args.next() {
+                    Some(p) => tty = Some(PathBuf::from(p)),
+                    None => {
+                        eprintln!("cobs-bridge: missing value for `{}`\n\n{}", arg, USAGE);
+                        return ExitCode::from(2);
+                    }
+                },
+                "-a" | "--ascii" => ascii = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    return ExitCode::SUCCESS;
+                }
+                _ => {
+                    eprintln!("cobs-bridge: unknown option `{}`\n\n{}", arg, USAGE);
+                    return ExitCode::from(2);
+                }
+            }
+        }
+
+        match run(tty, ascii) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("cobs-bridge: {}", e);
+                ExitCode::FAILURE
+            }
+        }
+    }
+
+    fn run(tty: Option<PathBuf>, ascii: bool) -> std::io::Result<()> {
+        let a = Pty::open()?;
+        println!("A: {}", a.path().display());
+
+        let (b, _pty) = match tty {
+            Some(path) => {
+                println!("B: {}", path.display());
+                (open_tty(&path)?, None)
+            }
+            None => {
+                let pty = Pty::open()?;
+                println!("B: {}", pty.path().display());
+                (pty.master().try_clone()?, Some(pty))
+            }
+        };
+
+        catch(libc::SIGINT)?;
+        catch(libc::SIGTERM)?;
+
+        let start = Instant::now();
+        let mut bridge = Bridge::new(a.master().try_clone()?, b);
+
+        bridge.run(&STOP, |direction, frame| {
+            let t = start.elapsed();
+            let arrow = match direction {
+                Direction::AtoB => "A->B",
+                Direction::BtoA => "B->A",
+            };
+
+            println!(
+                "[{:5}.{:06}] {} {:4} bytes: {}",
+                t.as_secs(),
+                t.subsec_micros(),
+                arrow,
+                frame.len(),
+                dump(frame, ascii)
+            );
+        })?;
+
+        for (direction, arrow) in [(Direction::AtoB, "A->B"), (Direction::BtoA, "B->A")] {
+            let s = bridge.sniffer(direction);
+            let (raw, encoded) = s.stats().get();
+
+            println!(
+                "{}: {} frames, {} decoded bytes, {} encoded bytes, {} pending bytes, {} dropped bytes",
+                arrow,
+                s.frames(),
+                raw,
+                encoded,
+                s.pending(),
+                s.dropped()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() -> std::process::ExitCode {
+    linux::main()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() -> std::process::ExitCode {
+    eprintln!("cobs-bridge: only supported on Linux");
+    std::process::ExitCode::FAILURE
+}
@@ -0,0 +1,567 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use libcobs::inspect::inspect;
+use libcobs::{frames, validate};
+use libcobs::send::{CobsSender, CobsSenderOperation};
+
+const USAGE: &str = "usage: cobs <encode|decode|inspect> [options] [FILE...]
+
+Reads FILEs (or stdin when none or `-` is given) and writes to stdout.
+
+options:
+  -i, --input <raw|hex|base64>       input format (default: raw)
+  -o, --output <raw|hex|base64>      output format (default: raw)
+  -d, --delimiter <line|none|0xNN>   raw frames are split on (encode) or
+                                     joined with (decode) this delimiter
+                                     (default: line)
+  -h, --help                         print this help
+
+hex and base64 inputs hold one item per line; hex and base64 outputs print
+one frame per line. inspect prints how each frame splits into code blocks.
+Errors in hex and base64 input give the line and the byte within that line.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Raw,
+    Hex,
+    Base64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Delimiter {
+    Byte(u8),
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Encode,
+    Decode,
+    Inspect,
+}
+
+struct Options {
+    mode: Mode,
+    input: Format,
+    output: Format,
+    delimiter: Delimiter,
+    files: Vec<String>,
+}
+
+struct Send2Mem {
+    data: Vec<u8>,
+}
+
+impl CobsSenderOperation for Send2Mem {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.data.extend_from_slice(buf);
+
+        Some(buf.len())
+    }
+}
+
+fn parse_format(s: &str) -> Result<Format, String> {
+    match s {
+        "raw" => Ok(Format::Raw),
+        "hex" => Ok(Format::Hex),
+        "base64" => Ok(Format::Base64),
+        _ => Err(format!("unknown format `{}`", s)),
+    }
+}
+
+fn parse_delimiter(s: &str) -> Result<Delimiter, String> {
+    match s {
+        "line" => Ok(Delimiter::Byte(b'\n')),
+        "none" => Ok(Delimiter::None),
+        _ => s
+            .strip_prefix("0x")
+            .and_then(|h| u8::from_str_radix(h, 16).ok())
+            .map(Delimiter::Byte)
+            .ok_or(format!("invalid delimiter `{}`", s)),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut args = args.iter();
+    let mode = match args.next().map(String::as_str) {
+        Some("encode") => Mode::Encode,
+        Some("decode") => Mode::Decode,
+        Some("inspect") => Mode::Inspect,
+        Some(m) => return Err(format!("unknown command `{}`", m)),
+        None => return Err(String::from("missing command")),
+    };
+
+    let mut options = Options {
+        mode,
+        input: Format::Raw,
+        output: Format::Raw,
+        delimiter: Delimiter::Byte(b'\n'),
+        files: vec![],
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+
+        match arg.as_str() {
+            "-i" | "--input" => options.input = parse_format(value()?)?,
+            "-o" | "--output" => options.output = parse_format(value()?)?,
+            "-d" | "--delimiter" => options.delimiter = parse_delimiter(value()?)?,
+            "-" => options.files.push(arg.clone()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ => options.files.push(arg.clone()),
+        }
+    }
+
+    Ok(options)
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The character of `text` at byte offset `i`, for error messages.
+fn char_at(text: &str, i: usize) -> char {
+    text[i..].chars().next().unwrap_or_default()
+}
+
+/// Errors come with the byte offset in `text` of the offending character.
+fn hex_decode(text: &str) -> Result<Vec<u8>, (usize, String)> {
+    let digits: Vec<(usize, u8)> = text.bytes().enumerate().filter(|(_, c)| !c.is_ascii_whitespace()).collect();
+
+    if let Some((i, _)) = digits.last().filter(|_| !digits.len().is_multiple_of(2)) {
+        return Err((*i, String::from("odd number of hex digits")));
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            pair.iter().try_fold(0u8, |v, (i, c)| match (*c as char).to_digit(16) {
+                Some(d) => Ok(v << 4 | d as u8),
+                None => Err((*i, format!("invalid hex digit `{}`", char_at(text, *i)))),
+            })
+        })
+        .collect()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+/// Errors come with the byte offset in `text` of the offending character.
+fn base64_decode(text: &str) -> Result<Vec<u8>, (usize, String)> {
+    let mut data: Vec<u8> = vec![];
+    let mut n: u32 = 0;
+    let mut bits = 0;
+
+    for (i, c) in text.bytes().enumerate().filter(|(_, c)| !c.is_ascii_whitespace() && *c != b'=') {
+        let v = BASE64
+            .iter()
+            .position(|b| *b == c)
+            .ok_or_else(|| (i, format!("invalid base64 character `{}`", char_at(text, i))))?;
+
+        n = (n << 6) | v as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            data.push((n >> bits) as u8);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Decode every non-empty line, along with its line number.
+///
+/// Errors give the byte offset in `input` of the offending character, then its line.
+fn parse_lines(input: &[u8], format: Format) -> Result<Vec<(usize, Vec<u8>)>, String> {
+    let text = std::str::from_utf8(input).map_err(|e| format!("offset {}: not UTF-8 text", e.valid_up_to()))?;
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| {
+            match format {
+                Format::Hex => hex_decode(l),
+                _ => base64_decode(l),
+            }
+            .map(|data| (n + 1, data))
+            .map_err(|(i, e)| {
+                let start = l.as_ptr() as usize - text.as_ptr() as usize;
+                format!("offset {}, line {}: {}", start + i, n + 1, e)
+            })
+        })
+        .collect()
+}
+
+/// Maps offsets in the stream being decoded back to the input file.
+#[derive(Default)]
+struct Origin {
+    /// Stream offset and line number of each hex or base64 line, empty for raw input.
+    lines: Vec<(usize, usize)>,
+}
+
+impl Origin {
+    /// Concatenate the lines into one stream, remembering where each one starts.
+    fn join(lines: Vec<(usize, Vec<u8>)>) -> (Vec<u8>, Origin) {
+        let mut stream: Vec<u8> = vec![];
+        let mut origin = Origin::default();
+
+        for (n, data) in lines {
+            origin.lines.push((stream.len(), n));
+            stream.extend_from_slice(&data);
+        }
+
+        (stream, origin)
+    }
+
+    fn locate(&self, offset: usize) -> String {
+        match self.lines.iter().rev().find(|(start, _)| *start <= offset) {
+            Some((start, n)) => format!("line {}, byte {}", n, offset - start),
+            None => format!("offset {}", offset),
+        }
+    }
+}
+
+fn write_frame(out: &mut Vec<u8>, frame: &[u8], format: Format, delimiter: Option<u8>) {
+    match format {
+        Format::Raw => {
+            out.extend_from_slice(frame);
+            if let Some(d) = delimiter {
+                out.push(d);
+            }
+        }
+        Format::Hex => {
+            out.extend_from_slice(hex_encode(frame).as_bytes());
+            out.push(b'\n');
+        }
+        Format::Base64 => {
+            out.extend_from_slice(base64_encode(frame).as_bytes());
+            out.push(b'\n');
+        }
+    }
+}
+
+fn encode(input: &[u8], options: &Options) -> Result<Vec<u8>, String> {
+    let frames: Vec<Vec<u8>> = match (options.input, options.delimiter) {
+        (Format::Raw, Delimiter::None) => vec![input.to_vec()],
+        (Format::Raw, Delimiter::Byte(d)) => {
+            let mut frames: Vec<Vec<u8>> = input.split(|b| *b == d).map(|f| f.to_vec()).collect();
+            if input.last() == Some(&d) || input.is_empty() {
+                frames.pop();
+            }
+            frames
+        }
+        (format, _) => parse_lines(input, format)?.into_iter().map(|(_, data)| data).collect(),
+    };
+
+    let mut out: Vec<u8> = vec![];
+
+    for frame in frames {
+        let mut s2m = Send2Mem { data: vec![] };
+        {
+            let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+            let mut s: CobsSender = CobsSender::new(&sender);
+            s.send(&frame).ok_or("send failed")?;
+        }
+
+        write_frame(&mut out, &s2m.data, options.output, None);
+    }
+
+    Ok(out)
+}
+
+/// Decode every frame of `stream`, returns the output and the errors met along the way with their offset.
+///
+/// Every zero ends a frame, so a malformed frame never hides the ones following it.
+fn decode(stream: &[u8], options: &Options) -> (Vec<u8>, Vec<(usize, String)>) {
+    let delimiter = match options.delimiter {
+        Delimiter::Byte(d) => Some(d),
+        Delimiter::None => None,
+    };
+    let mut it = frames(stream);
+    let mut out: Vec<u8> = vec![];
+    let mut errors: Vec<(usize, String)> = vec![];
+
+    loop {
+        let start = it.offset();
+
+        match it.next() {
+            None => break,
+            Some(Ok(f)) => write_frame(&mut out, &f, options.output, delimiter),
+            Some(Err(e)) => match validate(&stream[start..]) {
+                Err(a) => errors.push((start + a.offset(), a.to_string())),
+                // No anomaly to point at, the frame is still reported.
+                Ok(_) => errors.push((start, e.to_string())),
+            },
+        }
+    }
+
+    (out, errors)
+}
+
+/// Describe every frame of `stream`, anomalies are reported as errors too.
+fn analyse(stream: &[u8]) -> (Vec<u8>, Vec<(usize, String)>) {
+    let mut out: Vec<u8> = vec![];
+    let mut errors: Vec<(usize, String)> = vec![];
+
+    for report in inspect(stream) {
+        out.extend_from_slice(report.to_string().as_bytes());
+        errors.extend(report.anomalies.iter().map(|a| (a.offset(), a.to_string())));
+    }
+
+    (out, errors)
+}
+
+fn read(file: &str) -> io::Result<Vec<u8>> {
+    if file == "-" {
+        let mut buf: Vec<u8> = vec![];
+        io::stdin().read_to_end(&mut buf)?;
+        Ok(buf)
+    } else {
+        fs::read(file)
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let mut options = match parse_args(&args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("cobs: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if options.files.is_empty() {
+        options.files.push(String::from("-"));
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    let mut stdout = io::stdout().lock();
+
+    for file in &options.files {
+        let input = match read(file) {
+            Ok(i) => i,
+            Err(e) => {
+                eprintln!("cobs: {}: {}", file, e);
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+
+        let (out, errors) = match options.mode {
+            Mode::Encode => match encode(&input, &options) {
+                Ok(out) => (out, vec![]),
+                Err(e) => (vec![], vec![e]),
+            },
+            Mode::Decode | Mode::Inspect => {
+                let stream = match options.input {
+                    Format::Raw => Ok((input, Origin::default())),
+                    format => parse_lines(&input, format).map(Origin::join),
+                };
+
+                let (out, errors, origin) = match stream {
+                    Ok((stream, origin)) if options.mode == Mode::Decode => {
+                        let (out, errors) = decode(&stream, &options);
+                        (out, errors, origin)
+                    }
+                    Ok((stream, origin)) => {
+                        let (out, errors) = analyse(&stream);
+                        (out, errors, origin)
+                    }
+                    Err(e) => {
+                        eprintln!("cobs: {}: {}", file, e);
+                        status = ExitCode::FAILURE;
+                        continue;
+                    }
+                };
+
+                let errors = errors.into_iter().map(|(offset, e)| format!("{}: {}", origin.locate(offset), e));
+                (out, errors.collect())
+            }
+        };
+
+        for e in &errors {
+            eprintln!("cobs: {}: {}", file, e);
+            status = ExitCode::FAILURE;
+        }
+
+        if let Err(e) = stdout.write_all(&out) {
+            eprintln!("cobs: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        parse_args(&args).expect("args")
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let o = options(&["decode", "-i", "hex", "--output", "base64", "-d", "0x7e", "a.bin", "-"]);
+
+        assert_eq!(o.mode, Mode::Decode);
+        assert_eq!(o.input, Format::Hex);
+        assert_eq!(o.output, Format::Base64);
+        assert_eq!(o.delimiter, Delimiter::Byte(0x7e));
+        assert_eq!(o.files, vec!["a.bin", "-"]);
+
+        let args: Vec<String> = vec![String::from("encode"), String::from("-x")];
+        assert!(parse_args(&args).is_err());
+        assert!(parse_args(&[]).is_err());
+    }
+
+    #[test]
+    fn test_hex_base64() {
+        let data: Vec<u8> = (0..=0xff).collect();
+
+        assert_eq!(hex_encode(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(hex_decode("00 AB\t10"), Ok(vec![0x00, 0xab, 0x10]));
+        assert_eq!(hex_decode("00 1"), Err((3, String::from("odd number of hex digits"))));
+        assert_eq!(hex_decode("00 1z"), Err((4, String::from("invalid hex digit `z`"))));
+        assert_eq!(hex_decode("zz"), Err((0, String::from("invalid hex digit `z`"))));
+
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_decode(&base64_encode(&data)), Ok(data));
+        assert_eq!(base64_decode("Zm9v !"), Err((5, String::from("invalid base64 character `!`"))));
+    }
+
+    #[test]
+    fn test_encode_lines() {
+        let o = options(&["encode", "-o", "hex"]);
+
+        assert_eq!(encode(b"\x11\x00\n\n\x22", &o), Ok(b"02110100\n0100\n022200\n".to_vec()));
+    }
+
+    #[test]
+    fn test_encode_raw_whole() {
+        let o = options(&["encode", "-d", "none"]);
+
+        assert_eq!(encode(b"\x11\x00\n", &o), Ok(vec![0x02, 0x11, 0x02, 0x0a, 0x00]));
+    }
+
+    #[test]
+    fn test_encode_hex_input() {
+        let o = options(&["encode", "-i", "hex", "-o", "base64"]);
+
+        assert_eq!(encode(b"1100\n\n22\n", &o), Ok(b"AhEBAA==\nAiIA\n".to_vec()));
+        assert!(encode(b"1\n", &o).is_err());
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let o = options(&["decode"]);
+        let encoded = encode(b"abc\n\x00\x00\nz\n", &options(&["encode"])).expect("encode");
+
+        let (out, errors) = decode(&encoded, &o);
+        assert_eq!(out, b"abc\n\x00\x00\nz\n");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_analyse() {
+        let (out, errors) = analyse(&[0x02, 0x11, 0x00, 0x03, 0x00, 0x22, 0x00]);
+
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "frame @0: 3 encoded bytes, 1 decoded bytes\n  \
+             @0 code 0x02 run 1\n\
+             frame @3: 2 encoded bytes, 0 decoded bytes\n  \
+             @3 code 0x03 run 2\n  \
+             anomaly @4: zero inside a block\n\
+             frame @5: 2 encoded bytes, 0 decoded bytes\n  \
+             @5 code 0x22 run 33\n  \
+             anomaly @6: zero inside a block\n"
+        );
+        assert_eq!(
+            errors,
+            vec![(4, String::from("zero inside a block")), (6, String::from("zero inside a block"))]
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let o = options(&["decode", "-o", "hex"]);
+
+        let (out, errors) = decode(&[0x02, 0x11, 0x00, 0x04, 0x11, 0x00, 0x22, 0x00, 0x03, 0x33], &o);
+        assert_eq!(out, b"11\n");
+        assert_eq!(
+            errors,
+            vec![
+                (5, String::from("zero inside a block")),
+                (7, String::from("zero inside a block")),
+                (9, String::from("block truncated, 1 of 2 bytes")),
+            ]
+        );
+
+        // The good frame after a zero inside a block is still decoded.
+        let (out, errors) = decode(&[0x05, 0x11, 0x00, 0x02, 0x22, 0x00, 0x02, 0x33, 0x00], &o);
+        assert_eq!(out, b"22\n33\n");
+        assert_eq!(errors, vec![(2, String::from("zero inside a block"))]);
+    }
+
+    #[test]
+    fn test_origin() {
+        let lines = parse_lines(b"02 11 00\n\n04 11 00 22 00\n03 33\n", Format::Hex).expect("hex");
+        let (stream, origin) = Origin::join(lines);
+
+        assert_eq!(stream, [0x02, 0x11, 0x00, 0x04, 0x11, 0x00, 0x22, 0x00, 0x03, 0x33]);
+        assert_eq!(origin.locate(5), "line 3, byte 2");
+        assert_eq!(origin.locate(8), "line 4, byte 0");
+        assert_eq!(origin.locate(10), "line 4, byte 2");
+        assert_eq!(Origin::default().locate(8), "offset 8");
+    }
+
+    #[test]
+    fn test_parse_lines_errors() {
+        assert_eq!(
+            parse_lines(b"02 11 00\n\n04 1x 00\n", Format::Hex),
+            Err(String::from("offset 14, line 3: invalid hex digit `x`"))
+        );
+        assert_eq!(
+            parse_lines("AhEBAA==\nAi\u{e9}A\n".as_bytes(), Format::Base64),
+            Err(String::from("offset 11, line 2: invalid base64 character `\u{e9}`"))
+        );
+    }
+}
@@ -0,0 +1,524 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Byte bridge between two terminals with live frame sniffing (Linux only).
+//!
+//! `Bridge` forwards everything read on one side to the other side, and
+//! decodes the COBS frames seen in each direction the way `frames` does.
+
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::iter::frames;
+use super::send::max_encoded_len;
+use super::statistics::CobsStatistics;
+
+/// Largest payload `Sniffer::default` expects a frame to carry.
+pub const DEFAULT_MAX_FRAME: usize = 4096;
+
+/// Milliseconds a poll waits before `Bridge::run` checks its stop flag again.
+const POLL_MS: libc::c_int = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes read on side A and written to side B.
+    AtoB,
+    /// Bytes read on side B and written to side A.
+    BtoA,
+}
+
+/// Put the terminal behind `fd` in raw mode: no echo, no line editing, 8N1.
+pub(crate) fn make_raw(fd: &impl AsRawFd) -> io::Result<()> {
+    // SAFETY: termios is plain old data, all zeroes is a valid value.
+    let mut tio: libc::termios = unsafe { core::mem::zeroed() };
+
+    // SAFETY: `tio` is a valid termios to write to; a bad fd is reported through errno.
+    if unsafe { libc::tcgetattr(fd.as_raw_fd(), &mut tio) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: `tio` is a valid, initialised termios.
+    unsafe { libc::cfmakeraw(&mut tio) };
+
+    // SAFETY: `tio` is a valid termios, only read by the call.
+    if unsafe { libc::tcsetattr(fd.as_raw_fd(), libc::TCSANOW, &tio) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Pseudo-terminal pair in raw mode.
+///
+/// The bridge uses the master side; a program under test opens `path()`.
+pub struct Pty {
+    master: File,
+    slave: File,
+    path: PathBuf,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Pty> {
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        let mut name = [0 as libc::c_char; 64];
+
+        // SAFETY: the fd pointers are valid for writes, and `name` is zeroed and far larger
+        // than the `/dev/pts/N` path openpty copies into it with its NUL terminator. The
+        // null termios and winsize pointers are allowed and leave the defaults.
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                name.as_mut_ptr(),
+                core::ptr::null(),
+                core::ptr::null(),
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: openpty succeeded, so both are open descriptors that nothing else owns;
+        // each is wrapped exactly once and closed when its `File` is dropped.
+        let master = File::from(unsafe { OwnedFd::from_raw_fd(master) });
+        let slave = File::from(unsafe { OwnedFd::from_raw_fd(slave) });
+        // SAFETY: `name` holds a NUL-terminated string written by openpty, and was zeroed
+        // beforehand so the terminator is within the buffer; it outlives the borrow.
+        let path = PathBuf::from(
+            unsafe { CStr::from_ptr(name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+        );
+
+        make_raw(&slave)?;
+
+        Ok(Pty { master, slave, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn master(&self) -> &File {
+        &self.master
+    }
+
+    /// The slave side, kept open so the master never reports a hang-up.
+    pub fn slave(&self) -> &File {
+        &self.slave
+    }
+}
+
+/// Switch the file behind `fd` to non-blocking reads and writes.
+fn set_nonblocking(fd: &impl AsRawFd) -> io::Result<()> {
+    // SAFETY: F_GETFL takes no argument; a bad fd is reported through errno.
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    // SAFETY: F_SETFL takes the flags as its only argument.
+    if flags < 0 || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Open a real terminal device in raw mode.
+pub fn open_tty(path: &Path) -> io::Result<File> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    make_raw(&tty)?;
+
+    Ok(tty)
+}
+
+/// Incremental frame decoder for one direction of the bridge.
+///
+/// A frame growing past the encoded length of the largest expected payload is
+/// dropped, along with every byte up to the next zero.
+pub struct Sniffer {
+    pending: Vec<u8>,
+    stats: CobsStatistics,
+    frames: usize,
+    cap: usize,
+    // Dropping bytes up to the next zero.
+    skipping: bool,
+    dropped: usize,
+}
+
+impl Default for Sniffer {
+    fn default() -> Sniffer {
+        Sniffer::new(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl Sniffer {
+    /// Sniffer for frames carrying up to `max_frame` bytes.
+    pub fn new(max_frame: usize) -> Sniffer {
+        Sniffer {
+            pending: Vec::new(),
+            stats: CobsStatistics::default(),
+            frames: 0,
+            cap: max_encoded_len(max_frame),
+            skipping: false,
+            dropped: 0,
+        }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        &self.stats
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Bytes received so far that do not form a complete frame yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Bytes dropped with frames too long to be held.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Append `bytes` and return every frame they complete.
+    ///
+    /// Every zero ends a frame, so a malformed frame is dropped without
+    /// hiding the ones following it.
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Vec<Vec<u8>> {
+        if self.skipping {
+            let skip = match bytes.iter().position(|b| *b == 0) {
+                Some(i) => {
+                    self.skipping = false;
+                    i + 1
+                }
+                None => bytes.len(),
+            };
+            self.dropped += skip;
+            bytes = &bytes[skip..];
+        }
+
+        self.pending.extend_from_slice(bytes);
+
+        // The bytes after the last zero are a frame still on its way.
+        let end = match self.pending.iter().rposition(|b| *b == 0) {
+            Some(i) => i + 1,
+            None => {
+                self.cap_pending();
+                return vec![];
+            }
+        };
+
+        let mut it = frames(&self.pending[..end]);
+        let decoded: Vec<Vec<u8>> = it.by_ref().filter_map(Result::ok).map(Cow::into_owned).collect();
+
+        let (raw, encoded) = it.stats().get();
+        self.stats.update(raw, encoded);
+        self.frames += decoded.len();
+        self.pending.drain(..end);
+        self.cap_pending();
+
+        decoded
+    }
+
+    /// Drop the frame on its way once it outgrows the cap, and resync on the next zero.
+    fn cap_pending(&mut self) {
+        if self.pending.len() > self.cap {
+            self.dropped += self.pending.len();
+            self.pending.clear();
+            self.skipping = true;
+        }
+    }
+}
+
+/// Format `frame` as space separated hex bytes, or as an escaped ASCII string.
+pub fn dump(frame: &[u8], ascii: bool) -> String {
+    if ascii {
+        frame.escape_ascii().to_string()
+    } else {
+        frame
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+pub struct Bridge {
+    a: File,
+    b: File,
+    a_to_b: Sniffer,
+    b_to_a: Sniffer,
+}
+
+impl Bridge {
+    pub fn new(a: File, b: File) -> Bridge {
+        Bridge {
+            a,
+            b,
+            a_to_b: Sniffer::default(),
+            b_to_a: Sniffer::default(),
+        }
+    }
+
+    pub fn sniffer(&self, direction: Direction) -> &Sniffer {
+        match direction {
+            Direction::AtoB => &self.a_to_b,
+            Direction::BtoA => &self.b_to_a,
+        }
+    }
+
+    /// Forward bytes both ways until `stop` is set or one side hangs up.
+    ///
+    /// `on_frame` is called for every frame decoded in either direction. Both
+    /// sides are switched to non-blocking mode, so a side that stops reading
+    /// never keeps the bridge from noticing `stop`.
+    pub fn run(&mut self, stop: &AtomicBool, mut on_frame: impl FnMut(Direction, &[u8])) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+
+        set_nonblocking(&self.a)?;
+        set_nonblocking(&self.b)?;
+
+        while !stop.load(Ordering::Relaxed) {
+            let mut fds = [
+                libc::pollfd {
+                    fd: self.a.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.b.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+
+            if !poll(&mut fds)? {
+                continue;
+            }
+
+            for (i, fd) in fds.iter().enumerate() {
+                if fd.revents & libc::POLLIN != 0 {
+                    let (from, to, sniffer, direction) = if i == 0 {
+                        (&mut self.a, &mut self.b, &mut self.a_to_b, Direction::AtoB)
+                    } else {
+                        (&mut self.b, &mut self.a, &mut self.b_to_a, Direction::BtoA)
+                    };
+
+                    let n = match from.read(&mut buf) {
+                        Ok(0) => return Ok(()),
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                        Err(e) => return Err(e),
+                    };
+
+                    if !forward(to, &buf[..n], stop)? {
+                        return Ok(());
+                    }
+                    for f in sniffer.feed(&buf[..n]) {
+                        on_frame(direction, &f);
+                    }
+                } else if fd.revents & (libc::POLLHUP | libc::POLLERR) != 0 {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Wait up to `POLL_MS` on `fds`; false when interrupted by a signal.
+fn poll(fds: &mut [libc::pollfd]) -> io::Result<bool> {
+    // SAFETY: `fds` is a live slice of `fds.len()` pollfd entries.
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, POLL_MS) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(e);
+    }
+
+    Ok(true)
+}
+
+/// Write all of `data` to the non-blocking `to`, waiting for room between
+/// writes; false when `stop` is set first.
+fn forward(to: &mut File, mut data: &[u8], stop: &AtomicBool) -> io::Result<bool> {
+    while !data.is_empty() {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        match to.write(data) {
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let mut fds = [libc::pollfd {
+                    fd: to.as_raw_fd(),
+                    events: libc::POLLOUT,
+                    revents: 0,
+                }];
+                poll(&mut fds)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn read_exact(f: &mut File, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        f.read_exact(&mut data).expect("read");
+        data
+    }
+
+    #[test]
+    fn test_sniffer_partial_frames() {
+        let mut s = Sniffer::default();
+
+        assert!(s.feed(&[0x03, 0x11]).is_empty());
+        assert_eq!(s.pending(), 2);
+        assert_eq!(s.feed(&[0x22, 0x02, 0x33, 0x00, 0x01]), vec![vec![0x11, 0x22, 0x00, 0x33]]);
+        assert_eq!(s.feed(&[0x00, 0x02, 0x44, 0x00]), vec![vec![], vec![0x44]]);
+        assert_eq!(s.pending(), 0);
+        assert_eq!(s.frames(), 3);
+        assert_eq!(s.stats().get(), (5, 11));
+    }
+
+    #[test]
+    fn test_sniffer_resync() {
+        let mut s = Sniffer::default();
+
+        // The zero inside the first block ends it, the next frames are intact.
+        assert_eq!(s.feed(&[0x05, 0x11, 0x00, 0x02, 0x22, 0x00, 0x02, 0x33, 0x00]), vec![vec![0x22], vec![0x33]]);
+
+        // Same with the good frame split across two reads.
+        assert!(s.feed(&[0x04, 0x11, 0x00, 0x03, 0x44]).is_empty());
+        assert_eq!(s.feed(&[0x55, 0x00]), vec![vec![0x44, 0x55]]);
+        assert_eq!(s.pending(), 0);
+        assert_eq!(s.frames(), 3);
+        assert_eq!(s.stats().get(), (4, 10));
+    }
+
+    #[test]
+    fn test_sniffer_cap() {
+        let mut s = Sniffer::new(4);
+
+        // Six bytes still fit a 4-byte payload, the seventh does not.
+        assert!(s.feed(&[0x05, 0x11, 0x22, 0x33, 0x44, 0x01]).is_empty());
+        assert_eq!(s.pending(), 6);
+        assert!(s.feed(&[0x01, 0x01]).is_empty());
+        assert_eq!((s.pending(), s.dropped()), (0, 8));
+
+        // The rest of the long frame is dropped up to its zero, then decoding resumes.
+        assert_eq!(s.feed(&[0x01, 0x00, 0x02, 0x55, 0x00]), vec![vec![0x55]]);
+        assert_eq!((s.pending(), s.dropped()), (0, 10));
+        assert_eq!(s.frames(), 1);
+    }
+
+    #[test]
+    fn test_forward_stops() {
+        let a = Pty::open().expect("pty");
+        let mut master = a.master().try_clone().expect("clone");
+        set_nonblocking(&master).expect("nonblocking");
+
+        // Nobody reads the slave side: the writes fill it up, then the stop flag ends the wait.
+        let stop = AtomicBool::new(false);
+        let data = vec![0x55u8; 0x10000];
+        let mut full = false;
+        for _ in 0..64 {
+            match master.write(&data) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    full = true;
+                    break;
+                }
+                r => {
+                    r.expect("write");
+                }
+            }
+        }
+        assert!(full);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                stop.store(true, Ordering::Relaxed);
+            });
+            assert!(!forward(&mut master, &data, &stop).expect("forward"));
+        });
+    }
+
+    #[test]
+    fn test_dump() {
+        assert_eq!(dump(&[0x00, 0x41, 0xff], false), "00 41 ff");
+        assert_eq!(dump(b"hi\x00\n", true), "hi\\x00\\n");
+    }
+
+    #[test]
+    fn test_bridge_pty() {
+        let a = Pty::open().expect("pty");
+        let b = Pty::open().expect("pty");
+        let mut host = open_tty(a.path()).expect("open");
+        let mut device = open_tty(b.path()).expect("open");
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (bridge_a, bridge_b) = (a.master().try_clone().expect("clone"), b.master().try_clone().expect("clone"));
+        let flag = stop.clone();
+        let t = thread::spawn(move || {
+            let mut bridge = Bridge::new(bridge_a, bridge_b);
+            let mut frames: Vec<(Direction, Vec<u8>)> = vec![];
+
+            bridge
+                .run(&flag, |d, f| frames.push((d, f.to_vec())))
+                .expect("run");
+
+            let stats = (
+                bridge.sniffer(Direction::AtoB).stats().get(),
+                bridge.sniffer(Direction::BtoA).stats().get(),
+            );
+            (frames, stats)
+        });
+
+        host.write_all(&[0x03, 0x11, 0x22, 0x02, 0x33, 0x00]).expect("write");
+        assert_eq!(read_exact(&mut device, 6), vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00]);
+
+        device.write_all(&[0x01, 0x01, 0x00, 0x02]).expect("write");
+        assert_eq!(read_exact(&mut host, 4), vec![0x01, 0x01, 0x00, 0x02]);
+        device.write_all(&[0x44, 0x00]).expect("write");
+        assert_eq!(read_exact(&mut host, 2), vec![0x44, 0x00]);
+
+        thread::sleep(Duration::from_millis(100));
+        stop.store(true, Ordering::Relaxed);
+        let (frames, stats) = t.join().expect("join");
+
+        assert_eq!(
+            frames,
+            vec![
+                (Direction::AtoB, vec![0x11, 0x22, 0x00, 0x33]),
+                (Direction::BtoA, vec![0x00]),
+                (Direction::BtoA, vec![0x44]),
+            ]
+        );
+        assert_eq!(stats, ((4, 6), (2, 6)));
+    }
+}
@@ -0,0 +1,60 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Time sources for retransmission timers, recordings and captures.
+
+#[cfg(feature = "std")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of timestamps.
+///
+/// `ReliableLink` only needs it to be monotonic and counts its timeout in its
+/// ticks; `Recorder` and `PcapngWriter` expect microseconds.
+pub trait CobsClock {
+    fn now(&self) -> u64;
+}
+
+/// Microseconds elapsed since creation.
+#[cfg(feature = "std")]
+pub struct MonotonicClock(Instant);
+
+#[cfg(feature = "std")]
+impl Default for MonotonicClock {
+    fn default() -> MonotonicClock {
+        MonotonicClock(Instant::now())
+    }
+}
+
+#[cfg(feature = "std")]
+impl CobsClock for MonotonicClock {
+    fn now(&self) -> u64 {
+        self.0.elapsed().as_micros() as u64
+    }
+}
+
+/// Wall-clock microseconds since the Unix epoch; it may step backwards.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl CobsClock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_clock() {
+        // After 2020-01-01.
+        assert!(SystemClock.now() > 1_577_836_800_000_000);
+
+        let m = MonotonicClock::default();
+        let first = m.now();
+        assert!(m.now() >= first);
+    }
+}
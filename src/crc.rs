@@ -0,0 +1,278 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use super::error::CobsError;
+use super::recv::CobsReceiver;
+use super::send::CobsSender;
+use super::statistics::CobsStatistics;
+
+/// Checksum appended to the payload before stuffing.
+///
+/// The checksum bytes are written most significant byte first.
+pub trait CobsChecksum {
+    /// Number of bytes the checksum occupies at the end of a frame.
+    fn size(&self) -> usize;
+
+    /// Compute the checksum of `data` into `out` (`out.len() == self.size()`).
+    fn compute(&self, data: &[u8], out: &mut [u8]);
+}
+
+/// CRC-16/CCITT-FALSE: poly 0x1021, init 0xffff, no reflection.
+#[derive(Default, Clone, Copy)]
+pub struct Crc16Ccitt;
+
+impl Crc16Ccitt {
+    pub fn checksum(data: &[u8]) -> u16 {
+        let mut crc: u16 = 0xffff;
+
+        for b in data {
+            crc ^= (*b as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                };
+            }
+        }
+
+        crc
+    }
+}
+
+impl CobsChecksum for Crc16Ccitt {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn compute(&self, data: &[u8], out: &mut [u8]) {
+        out.copy_from_slice(&Crc16Ccitt::checksum(data).to_be_bytes());
+    }
+}
+
+/// CRC-32 (ISO-HDLC, as used by Ethernet and zlib).
+#[derive(Default, Clone, Copy)]
+pub struct Crc32;
+
+impl Crc32 {
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc: u32 = 0xffff_ffff;
+
+        for b in data {
+            crc ^= *b as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+
+        !crc
+    }
+}
+
+impl CobsChecksum for Crc32 {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn compute(&self, data: &[u8], out: &mut [u8]) {
+        out.copy_from_slice(&Crc32::checksum(data).to_be_bytes());
+    }
+}
+
+/// Length of the payload of `data`, once the checksum at its end is checked and removed.
+pub(crate) fn payload_len<C: CobsChecksum>(checksum: &C, data: &[u8]) -> Result<usize, CobsError> {
+    let size = checksum.size();
+
+    if data.len() < size {
+        return Err(CobsError::Crc);
+    }
+
+    let end = data.len() - size;
+    let mut expected: Vec<u8> = alloc::vec![0; size];
+    checksum.compute(&data[..end], &mut expected);
+
+    if data[end..] != expected[..] {
+        return Err(CobsError::Crc);
+    }
+
+    Ok(end)
+}
+
+pub struct CrcSender<'l, C: CobsChecksum> {
+    sender: CobsSender<'l>,
+    checksum: C,
+}
+
+impl<'l, C: CobsChecksum> CrcSender<'l, C> {
+    pub fn new(sender: CobsSender<'l>, checksum: C) -> CrcSender<'l, C> {
+        CrcSender { sender, checksum }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.sender.stats()
+    }
+
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize, CobsError> {
+        let mut frame: Vec<u8> = Vec::with_capacity(buf.len() + self.checksum.size());
+
+        frame.extend_from_slice(buf);
+        frame.resize(buf.len() + self.checksum.size(), 0);
+        self.checksum.compute(buf, &mut frame[buf.len()..]);
+
+        self.sender.send(&frame).ok_or(CobsError::Send)
+    }
+}
+
+pub struct CrcReceiver<'l, C: CobsChecksum> {
+    receiver: CobsReceiver<'l>,
+    checksum: C,
+}
+
+impl<'l, C: CobsChecksum> CrcReceiver<'l, C> {
+    pub fn new(receiver: CobsReceiver<'l>, checksum: C) -> CrcReceiver<'l, C> {
+        CrcReceiver { receiver, checksum }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.receiver.stats()
+    }
+
+    /// Receive a frame and strip its checksum.
+    ///
+    /// An idle link or an empty frame is `CobsError::Recv`, only a frame that
+    /// fails its check counts as a checksum error.
+    pub fn recv(&mut self) -> Result<Vec<u8>, CobsError> {
+        let mut data = self.receiver.recv().ok_or(CobsError::Recv)?;
+
+        if data.is_empty() {
+            return Err(CobsError::Recv);
+        }
+
+        match payload_len(&self.checksum, &data) {
+            Ok(end) => {
+                data.truncate(end);
+                Ok(data)
+            }
+            Err(e) => {
+                self.receiver.stats_mut().crc_error();
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiverOperation;
+    use crate::testutil::{sent, Mem2Recv};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    fn encode<C: CobsChecksum>(pattern: &[u8], checksum: C) -> Vec<u8> {
+        sent(|sender| {
+            let mut s = CrcSender::new(sender, checksum);
+
+            let l = s.send(pattern).expect("send");
+            let (raw, enc) = s.stats().get();
+            assert_eq!(raw, pattern.len() + s.checksum.size());
+            assert_eq!(enc, l);
+        })
+    }
+
+    #[test]
+    fn test_crc16_check() {
+        assert_eq!(Crc16Ccitt::checksum(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn test_crc32_check() {
+        assert_eq!(Crc32::checksum(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_crc16_roundtrip() {
+        let pattern: Vec<u8> = vec![0x11, 0x00, 0x22, 0x00];
+        let encoded = encode(&pattern, Crc16Ccitt);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = CrcReceiver::new(CobsReceiver::new(&receiver), Crc16Ccitt);
+
+        assert_eq!(r.recv(), Ok(pattern));
+        assert_eq!(r.stats().crc_errors(), 0);
+    }
+
+    #[test]
+    fn test_crc32_roundtrip() {
+        let pattern: Vec<u8> = (0..=0xff).collect::<Vec<_>>();
+        let encoded = encode(&pattern, Crc32);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = CrcReceiver::new(CobsReceiver::new(&receiver), Crc32);
+
+        assert_eq!(r.recv(), Ok(pattern));
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut encoded = encode(&[0x11, 0x22, 0x33], Crc32);
+        encoded[2] ^= 0x40;
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = CrcReceiver::new(CobsReceiver::new(&receiver), Crc32);
+
+        assert_eq!(r.recv(), Err(CobsError::Crc));
+        assert_eq!(r.stats().crc_errors(), 1);
+    }
+
+    #[test]
+    fn test_crc_short_frame() {
+        let encoded: &[u8] = &[0x02, 0x11, 0x00];
+
+        let mut m2r = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = CrcReceiver::new(CobsReceiver::new(&receiver), Crc16Ccitt);
+
+        assert_eq!(r.recv(), Err(CobsError::Crc));
+        assert_eq!(r.stats().crc_errors(), 1);
+    }
+
+    struct Idle;
+
+    impl CobsReceiverOperation for Idle {
+        fn recv(&mut self, _len: usize) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_crc_idle_link() {
+        let mut idle = Idle;
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut idle));
+        let mut r = CrcReceiver::new(CobsReceiver::new(&receiver), Crc16Ccitt);
+
+        for _ in 0..3 {
+            assert_eq!(r.recv(), Err(CobsError::Recv));
+        }
+        assert_eq!(r.stats().crc_errors(), 0);
+
+        // An empty frame carries no checksum to check either.
+        let mut m2r = Mem2Recv::new(&[0x01, 0x00]);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = CrcReceiver::new(CobsReceiver::new(&receiver), Crc16Ccitt);
+
+        assert_eq!(r.recv(), Err(CobsError::Recv));
+        assert_eq!(r.stats().crc_errors(), 0);
+    }
+}
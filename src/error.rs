@@ -0,0 +1,98 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CobsError {
+    /// The underlying `CobsSenderOperation` failed to emit the bytes.
+    Send,
+    /// The underlying `CobsReceiverOperation` ran dry before a frame ended.
+    Recv,
+    /// The checksum carried by the frame does not match its payload.
+    Crc,
+    /// The message authentication code does not match the frame.
+    Auth,
+    /// The frame counter did not increase, the frame is a replay.
+    Replay,
+    /// No room is left to queue the frame, retry later.
+    Busy,
+    /// The frame was addressed to a channel nobody listens to.
+    Channel,
+    /// The value could not be serialized into a frame.
+    Serialize,
+    /// The frame does not hold a valid serialized value.
+    Deserialize,
+    /// The buffer is too small for the frame.
+    Overflow,
+    /// A layer was set up with a parameter out of its range.
+    Config,
+}
+
+impl fmt::Display for CobsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CobsError::Send => write!(f, "cobs: send failed"),
+            CobsError::Recv => write!(f, "cobs: receive failed"),
+            CobsError::Crc => write!(f, "cobs: checksum mismatch"),
+            CobsError::Auth => write!(f, "cobs: authentication failed"),
+            CobsError::Replay => write!(f, "cobs: replayed frame"),
+            CobsError::Busy => write!(f, "cobs: busy"),
+            CobsError::Channel => write!(f, "cobs: unknown channel"),
+            CobsError::Serialize => write!(f, "cobs: serialization failed"),
+            CobsError::Deserialize => write!(f, "cobs: deserialization failed"),
+            CobsError::Overflow => write!(f, "cobs: buffer too small"),
+            CobsError::Config => write!(f, "cobs: invalid configuration"),
+        }
+    }
+}
+
+impl core::error::Error for CobsError {}
+
+/// Why a frame does not decode, with the offset where it goes wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A zero byte sits inside a code block, at this offset.
+    ZeroInBlock { offset: usize },
+    /// The input ends inside a code block.
+    TruncatedBlock { offset: usize, expected: usize, got: usize },
+    /// The input ends before the frame delimiter.
+    MissingDelimiter { offset: usize },
+}
+
+impl Anomaly {
+    /// Offset in the input where the frame goes wrong.
+    pub fn offset(&self) -> usize {
+        match *self {
+            Anomaly::ZeroInBlock { offset }
+            | Anomaly::TruncatedBlock { offset, .. }
+            | Anomaly::MissingDelimiter { offset } => offset,
+        }
+    }
+
+    /// The same anomaly, for a frame found `start` bytes further in the input.
+    pub(crate) fn moved(self, start: usize) -> Anomaly {
+        match self {
+            Anomaly::ZeroInBlock { offset } => Anomaly::ZeroInBlock { offset: start + offset },
+            Anomaly::TruncatedBlock { offset, expected, got } => Anomaly::TruncatedBlock {
+                offset: start + offset,
+                expected,
+                got,
+            },
+            Anomaly::MissingDelimiter { offset } => Anomaly::MissingDelimiter { offset: start + offset },
+        }
+    }
+}
+
+/// Describe the anomaly, without its offset.
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::ZeroInBlock { .. } => write!(f, "zero inside a block"),
+            Anomaly::TruncatedBlock { expected, got, .. } => {
+                write!(f, "block truncated, {} of {} bytes", got, expected)
+            }
+            Anomaly::MissingDelimiter { .. } => write!(f, "missing delimiter at end of input"),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Capture analysis.
+//!
+//! `inspect` splits a raw capture at every zero, the way `frames` does, then
+//! follows the code bytes of each frame the way `validate` does to explain how
+//! it was split into code blocks.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::error::Anomaly;
+use super::recv::walk;
+use super::zero::find_zero;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// Offset of the code byte in the capture.
+    pub offset: usize,
+    pub code: u8,
+    /// Number of data bytes following the code byte.
+    pub run: usize,
+    /// Offset in the decoded frame where a zero is restored after this block.
+    pub zero_at: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameReport {
+    /// Offset of the first byte of the frame in the capture.
+    pub offset: usize,
+    pub encoded_len: usize,
+    pub decoded_len: usize,
+    pub blocks: Vec<CodeBlock>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Describe the frame starting at `start`, up to the next zero or the end of `data`.
+fn report(data: &[u8], start: usize) -> FrameReport {
+    let stop = find_zero(&data[start..]).map_or(data.len(), |i| start + i);
+    let mut r = FrameReport {
+        offset: start,
+        encoded_len: (stop + 1).min(data.len()) - start,
+        decoded_len: 0,
+        blocks: Vec::new(),
+        anomalies: Vec::new(),
+    };
+
+    let walked = walk(&data[start..], true, |offset, code, run| {
+        if let Some(prev) = r.blocks.last_mut() {
+            if prev.code != 0xff {
+                prev.zero_at = Some(r.decoded_len);
+                r.decoded_len += 1;
+            }
+        }
+
+        r.blocks.push(CodeBlock {
+            offset: start + offset,
+            code,
+            run: code as usize - 1,
+            zero_at: None,
+        });
+        r.decoded_len += run.len();
+    });
+
+    if let Err(a) = walked {
+        r.anomalies.push(a.moved(start));
+    }
+
+    // A block cut by the end of the capture is missing its delimiter too.
+    if let Err(Anomaly::TruncatedBlock { .. }) = walked {
+        r.anomalies.push(Anomaly::MissingDelimiter { offset: data.len() });
+    }
+
+    r
+}
+
+/// Split `capture` into frames and describe each of them.
+///
+/// Every zero ends a frame, even inside a block, so a malformed frame never
+/// hides the ones following it.
+pub fn inspect(capture: &[u8]) -> Vec<FrameReport> {
+    let mut reports: Vec<FrameReport> = Vec::new();
+    let mut offset = 0;
+
+    while offset < capture.len() {
+        let report = report(capture, offset);
+
+        offset += report.encoded_len;
+        reports.push(report);
+    }
+
+    reports
+}
+
+impl fmt::Display for FrameReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "frame @{}: {} encoded bytes, {} decoded bytes",
+            self.offset, self.encoded_len, self.decoded_len
+        )?;
+
+        for b in &self.blocks {
+            write!(f, "  @{} code 0x{:02x} run {}", b.offset, b.code, b.run)?;
+            if let Some(z) = b.zero_at {
+                write!(f, ", zero restored at {}", z)?;
+            }
+            writeln!(f)?;
+        }
+
+        for a in &self.anomalies {
+            writeln!(f, "  anomaly @{}: {}", a.offset(), a)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_blocks() {
+        let capture: &[u8] = &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x01, 0x01, 0x00];
+        let reports = inspect(capture);
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(
+            reports[0],
+            FrameReport {
+                offset: 0,
+                encoded_len: 6,
+                decoded_len: 4,
+                blocks: vec![
+                    CodeBlock { offset: 0, code: 0x03, run: 2, zero_at: Some(2) },
+                    CodeBlock { offset: 3, code: 0x02, run: 1, zero_at: None },
+                ],
+                anomalies: vec![],
+            }
+        );
+        assert_eq!(reports[1].offset, 6);
+        assert_eq!(reports[1].decoded_len, 1);
+        assert_eq!(reports[1].blocks[0].zero_at, Some(0));
+    }
+
+    #[test]
+    fn test_inspect_long_block() {
+        let mut capture: Vec<u8> = vec![0xff];
+        capture.append(&mut (1..=0xfe).collect::<Vec<_>>());
+        capture.append(&mut vec![0x02, 0xff, 0x00]);
+
+        let reports = inspect(&capture);
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].decoded_len, 255);
+        assert_eq!(reports[0].blocks[0].run, 254);
+        assert_eq!(reports[0].blocks[0].zero_at, None);
+        assert_eq!(reports[0].blocks[1].offset, 255);
+        assert!(reports[0].anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_inspect_zero_in_block() {
+        let capture: &[u8] = &[0x04, 0x11, 0x00, 0x22, 0x00];
+        let reports = inspect(capture);
+
+        assert_eq!(reports[0].anomalies, vec![Anomaly::ZeroInBlock { offset: 2 }]);
+        assert_eq!(reports[0].encoded_len, 3);
+        assert_eq!(reports[1].offset, 3);
+    }
+
+    #[test]
+    fn test_inspect_resync() {
+        // The zero inside the first block ends it, the next frames are intact.
+        let capture: &[u8] = &[0x05, 0x11, 0x00, 0x02, 0x22, 0x00, 0x02, 0x33, 0x00];
+        let reports = inspect(capture);
+
+        assert_eq!(
+            reports.iter().map(|r| (r.offset, r.encoded_len, r.decoded_len)).collect::<Vec<_>>(),
+            vec![(0, 3, 1), (3, 3, 1), (6, 3, 1)]
+        );
+        assert_eq!(reports[0].anomalies, vec![Anomaly::ZeroInBlock { offset: 2 }]);
+        assert!(reports[1].anomalies.is_empty() && reports[2].anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_inspect_missing_delimiter() {
+        let capture: &[u8] = &[0x02, 0x11, 0x00, 0x02, 0x22];
+        let reports = inspect(capture);
+
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0].anomalies.is_empty());
+        assert_eq!(reports[1].anomalies, vec![Anomaly::MissingDelimiter { offset: 5 }]);
+    }
+
+    #[test]
+    fn test_inspect_truncated_block() {
+        let capture: &[u8] = &[0x05, 0x11, 0x22];
+        let reports = inspect(capture);
+
+        assert_eq!(
+            reports[0].anomalies,
+            vec![
+                Anomaly::TruncatedBlock { offset: 1, expected: 4, got: 2 },
+                Anomaly::MissingDelimiter { offset: 3 },
+            ]
+        );
+        assert_eq!(reports[0].decoded_len, 2);
+    }
+
+    #[test]
+    fn test_inspect_ends_after_code() {
+        for capture in [&[0x02][..], &[0x01, 0x00, 0x03, 0x11, 0x22, 0x02]] {
+            let last = inspect(capture).pop().expect("report");
+
+            assert_eq!(last.anomalies.last(), Some(&Anomaly::MissingDelimiter { offset: capture.len() }));
+        }
+    }
+
+    #[test]
+    fn test_inspect_display() {
+        let reports = inspect(&[0x03, 0x11, 0x22, 0x01, 0x00, 0x02]);
+        let text = format!("{}{}", reports[0], reports[1]);
+
+        assert_eq!(
+            text,
+            "frame @0: 5 encoded bytes, 3 decoded bytes\n  \
+             @0 code 0x03 run 2, zero restored at 2\n  \
+             @3 code 0x01 run 0\n\
+             frame @5: 1 encoded bytes, 0 decoded bytes\n  \
+             @5 code 0x02 run 1\n  \
+             anomaly @6: block truncated, 0 of 1 bytes\n  \
+             anomaly @6: missing delimiter at end of input\n"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Iterators over the frames held in a buffer or read from a transport.
+//!
+//! A malformed frame yields `Err(CobsError::Recv)` and iteration resumes after
+//! its delimiter, so one bad frame never hides the following ones.
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::error::CobsError;
+use super::recv::{unstuff, CobsReceiverOperation};
+use super::statistics::CobsStatistics;
+
+/// A decoded frame, borrowed from the input when it is a single code block.
+pub type Frame<'a> = Cow<'a, [u8]>;
+
+/// Decode one frame, `encoded` being the bytes before its delimiter.
+///
+/// `encoded` holds no zero, so following its code bytes is enough: a single
+/// pass checks and decodes it.
+pub(crate) fn decode_frame(encoded: &[u8]) -> Result<Frame<'_>, CobsError> {
+    if encoded.is_empty() {
+        return Ok(Cow::Borrowed(&[]));
+    }
+
+    if encoded[0] as usize == encoded.len() {
+        return Ok(Cow::Borrowed(&encoded[1..]));
+    }
+
+    let mut data: Vec<u8> = vec![0; encoded.len()];
+    let len = unstuff(encoded, &mut data)?;
+    data.truncate(len);
+
+    Ok(Cow::Owned(data))
+}
+
+pub struct Frames<'a> {
+    data: &'a [u8],
+    offset: usize,
+    stats: CobsStatistics,
+}
+
+/// Iterate over the frames encoded in `data`.
+///
+/// Bytes after the last delimiter are reported as a malformed frame.
+pub fn frames(data: &[u8]) -> Frames<'_> {
+    Frames {
+        data,
+        offset: 0,
+        stats: CobsStatistics::default(),
+    }
+}
+
+impl Frames<'_> {
+    /// Offset in the input of the next frame.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Totals of the frames decoded successfully.
+    pub fn stats(&self) -> &CobsStatistics {
+        &self.stats
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>, CobsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.data[self.offset..];
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (encoded, consumed) = match rest.iter().position(|b| *b == 0) {
+            Some(i) => (&rest[..i], i + 1),
+            None => {
+                self.offset = self.data.len();
+                return Some(Err(CobsError::Recv));
+            }
+        };
+        self.offset += consumed;
+
+        let frame = decode_frame(encoded);
+        if let Ok(f) = &frame {
+            self.stats.update(f.len(), consumed);
+        }
+
+        Some(frame)
+    }
+}
+
+pub struct StreamFrames<'l> {
+    receiver: &'l mut dyn CobsReceiverOperation,
+    // Bytes read past the zero that cut a malformed frame short.
+    pending: Vec<u8>,
+    done: bool,
+    stats: CobsStatistics,
+}
+
+/// Iterate over the frames read from `receiver` until it returns `None`.
+///
+/// Like `CobsReceiver::recv`, each frame is read one code byte and then one
+/// block at a time, so nothing past its delimiter is taken from `receiver`.
+pub fn stream_frames(receiver: &mut dyn CobsReceiverOperation) -> StreamFrames<'_> {
+    StreamFrames {
+        receiver,
+        pending: Vec::new(),
+        done: false,
+        stats: CobsStatistics::default(),
+    }
+}
+
+impl StreamFrames<'_> {
+    /// Totals of the frames decoded successfully.
+    pub fn stats(&self) -> &CobsStatistics {
+        &self.stats
+    }
+
+    fn read(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return self.receiver.recv(len);
+        }
+
+        let length = len.min(self.pending.len());
+
+        Some(self.pending.drain(..length).collect())
+    }
+}
+
+impl Iterator for StreamFrames<'_> {
+    type Item = Result<Vec<u8>, CobsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut encoded: Vec<u8> = Vec::new();
+
+        loop {
+            let code = match self.read(1) {
+                Some(c) => match c.first() {
+                    Some(c) => *c,
+                    None => return Some(Err(CobsError::Recv)),
+                },
+                None if encoded.is_empty() => {
+                    self.done = true;
+                    return None;
+                }
+                None => {
+                    self.done = true;
+                    return Some(Err(CobsError::Recv));
+                }
+            };
+
+            if code == 0 {
+                let frame = decode_frame(&encoded).map(Cow::into_owned);
+
+                if let Ok(f) = &frame {
+                    self.stats.update(f.len(), encoded.len() + 1);
+                }
+
+                return Some(frame);
+            }
+            encoded.push(code);
+
+            let mut block = code as usize - 1;
+            while block > 0 {
+                let mut buf = match self.read(block) {
+                    Some(b) if !b.is_empty() => b,
+                    _ => {
+                        self.done = true;
+                        return Some(Err(CobsError::Recv));
+                    }
+                };
+
+                // A zero inside the block ends the frame, what follows it starts the next one.
+                if let Some(i) = buf.iter().position(|b| *b == 0) {
+                    buf.extend_from_slice(&self.pending);
+                    self.pending = buf.split_off(i + 1);
+
+                    return Some(Err(CobsError::Recv));
+                }
+
+                block -= buf.len();
+                encoded.append(&mut buf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{encode, Mem2Recv};
+
+    fn payloads() -> Vec<Vec<u8>> {
+        vec![
+            vec![],
+            vec![0x00],
+            vec![0x11, 0x22, 0x00, 0x33],
+            (1..=0xfe).collect(),
+            (1..=0xfe).chain([0x00]).collect(),
+            (0..600).map(|i| (i % 5) as u8).collect(),
+        ]
+    }
+
+    #[test]
+    fn test_frames_roundtrip() {
+        let encoded = encode(&payloads());
+        let mut it = frames(&encoded);
+        let decoded: Vec<Vec<u8>> = it.by_ref().map(|f| f.expect("frame").into_owned()).collect();
+
+        assert_eq!(decoded, payloads());
+        assert_eq!(it.offset(), encoded.len());
+        assert_eq!(it.stats().get(), (payloads().iter().map(Vec::len).sum(), encoded.len()));
+    }
+
+    #[test]
+    fn test_frames_borrowed() {
+        let encoded = [0x03, 0x11, 0x22, 0x00, 0x02, 0x33, 0x01, 0x00];
+        let frames: Vec<Frame> = frames(&encoded).map(|f| f.expect("frame")).collect();
+
+        assert!(matches!(frames[0], Cow::Borrowed(&[0x11, 0x22])));
+        assert!(matches!(frames[1], Cow::Owned(_)));
+        assert_eq!(*frames[1], [0x33, 0x00]);
+    }
+
+    #[test]
+    fn test_frames_errors() {
+        // Block overrunning its delimiter, good frame, trailing bytes.
+        let encoded = [0x05, 0x11, 0x00, 0x02, 0x22, 0x00, 0x02, 0x33];
+        let frames: Vec<Result<Vec<u8>, CobsError>> = frames(&encoded).map(|f| f.map(Cow::into_owned)).collect();
+
+        assert_eq!(frames, vec![Err(CobsError::Recv), Ok(vec![0x22]), Err(CobsError::Recv)]);
+    }
+
+    #[test]
+    fn test_stream_frames() {
+        let mut encoded = encode(&payloads());
+        encoded.extend_from_slice(&[0x04, 0x11, 0x00, 0x02, 0x44]);
+
+        let mut m2r = Mem2Recv::chunked(&encoded, 3);
+        let mut it = stream_frames(&mut m2r);
+        let mut expected: Vec<Result<Vec<u8>, CobsError>> = payloads().into_iter().map(Ok).collect();
+        expected.push(Err(CobsError::Recv));
+        expected.push(Err(CobsError::Recv));
+
+        assert_eq!(it.by_ref().collect::<Vec<_>>(), expected);
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn test_stream_frames_resync() {
+        // The zero inside the first block starts the next frame.
+        let encoded = [0x05, 0x11, 0x00, 0x02, 0x22, 0x00, 0x02, 0x33, 0x00];
+
+        for chunk in [1, 3, 8] {
+            let mut m2r = Mem2Recv::chunked(&encoded, chunk);
+            let frames: Vec<Result<Vec<u8>, CobsError>> = stream_frames(&mut m2r).collect();
+
+            assert_eq!(frames, vec![Err(CobsError::Recv), Ok(vec![0x22]), Ok(vec![0x33])], "chunk {}", chunk);
+        }
+    }
+
+    #[cfg(all(unix, feature = "std"))]
+    #[test]
+    fn test_stream_frames_socket() {
+        use crate::recv::CobsReceiver;
+        use std::cell::RefCell;
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+        use std::rc::Rc;
+        use std::time::{Duration, Instant};
+
+        let (mut a, mut b) = UnixStream::pair().expect("pair");
+        b.set_read_timeout(Some(Duration::from_secs(5))).expect("timeout");
+
+        // Two frames on a stream left open: the first must not wait for more bytes.
+        a.write_all(&[0x02, 0x11, 0x00, 0x02, 0x22, 0x00]).expect("write");
+        let start = Instant::now();
+        assert_eq!(stream_frames(&mut b).next(), Some(Ok(vec![0x11])));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // The second frame is still there for another consumer.
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut b));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        assert_eq!(r.recv(), Some(vec![0x22]));
+    }
+}
@@ -1,9 +1,42 @@
 // SPDX Licence-Identifier: Apache-2.0
 // SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
 
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod bridge;
+pub mod clock;
+pub mod crc;
+pub mod error;
+pub mod inspect;
+pub mod iter;
+#[cfg(feature = "std")]
+pub mod log;
+#[cfg(feature = "serde")]
+mod message;
+pub mod mux;
+#[cfg(feature = "std")]
+pub mod net;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod pcapng;
+#[cfg(feature = "std")]
+pub mod record;
 pub mod recv;
+pub mod reliable;
 pub mod send;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod serial;
 pub mod statistics;
+#[cfg(test)]
+pub(crate) mod testutil;
+mod zero;
 
+pub use iter::frames;
+pub use recv::{decode, decode_in_place, decode_trusted, decoded_len, max_decoded_len, validate};
+pub use send::{encode_headroom, encode_in_place, encode_iter, encode_to_array, encoded_len, max_encoded_len};
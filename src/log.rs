@@ -0,0 +1,474 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Append-only log of COBS frames.
+//!
+//! Each record is stored as one frame followed by its zero delimiter, with an
+//! optional CRC-32 appended to the payload before stuffing. Since a zero only
+//! ever appears as a delimiter, a reader resynchronises on the next record
+//! after a damaged one, and bytes after the last delimiter are a record torn by
+//! a power loss: they are ignored, then overwritten by the next append, which
+//! cuts the file right after its own record.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+use super::crc::{payload_len, Crc32, CrcSender};
+use super::error::CobsError;
+use super::recv::{self, validate};
+use super::send::{CobsSender, CobsSenderOperation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogOptions {
+    /// Append a CRC-32 to every record and check it on read.
+    pub crc: bool,
+    /// Remember the offset of one record every `index_every` to speed up `iter_from`.
+    pub index_every: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> LogOptions {
+        LogOptions {
+            crc: true,
+            index_every: 64,
+        }
+    }
+}
+
+/// Storage a log can cut short, to drop what is left of a torn record.
+pub trait Truncate {
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl Truncate for File {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+}
+
+impl Truncate for Cursor<Vec<u8>> {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().truncate(len as usize);
+        Ok(())
+    }
+}
+
+struct Frame(Vec<u8>);
+
+impl CobsSenderOperation for Frame {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.0.extend_from_slice(buf);
+        Some(buf.len())
+    }
+}
+
+/// Decode one stored record, delimiter included.
+fn decode(stored: &[u8], crc: bool) -> Result<Vec<u8>, CobsError> {
+    // The writer never stores a bare delimiter.
+    if stored.len() < 2 {
+        return Err(CobsError::Recv);
+    }
+
+    let len = validate(stored).map_err(|_| CobsError::Recv)?;
+    let mut data = vec![0u8; len];
+    recv::decode(stored, &mut data)?;
+
+    if crc {
+        let end = payload_len(&Crc32, &data)?;
+        data.truncate(end);
+    }
+
+    Ok(data)
+}
+
+pub struct CobsLog<F: Read + Write + Seek + Truncate> {
+    file: F,
+    options: LogOptions,
+    /// Offset just past the last delimiter.
+    end: u64,
+    records: usize,
+    torn: u64,
+    /// Bytes of the torn record still in the file past `end`.
+    tail: u64,
+    /// Offset of records 0, `index_every`, `2 * index_every`...
+    index: Vec<u64>,
+}
+
+impl<F: Read + Write + Seek + Truncate> CobsLog<F> {
+    /// Scan `file` to find where its last complete record ends.
+    pub fn open(mut file: F, options: LogOptions) -> io::Result<CobsLog<F>> {
+        if options.index_every == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "index_every must not be zero"));
+        }
+
+        let length = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut log = CobsLog {
+            file,
+            options,
+            end: 0,
+            records: 0,
+            torn: 0,
+            tail: 0,
+            index: Vec::new(),
+        };
+
+        let mut buf = [0u8; 4096];
+        let mut offset: u64 = 0;
+        let mut start: u64 = 0;
+
+        loop {
+            let n = match log.file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            for (i, b) in buf[..n].iter().enumerate() {
+                if *b == 0 {
+                    log.push(start);
+                    start = offset + i as u64 + 1;
+                }
+            }
+
+            offset += n as u64;
+        }
+
+        log.end = start;
+        log.torn = length - start;
+        log.tail = log.torn;
+
+        Ok(log)
+    }
+
+    fn push(&mut self, start: u64) {
+        if self.records.is_multiple_of(self.options.index_every) {
+            self.index.push(start);
+        }
+
+        self.records += 1;
+    }
+
+    /// Number of complete records, damaged ones included.
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Size of the torn record found at the end of the file when it was opened.
+    pub fn torn(&self) -> u64 {
+        self.torn
+    }
+
+    pub fn append(&mut self, record: &[u8]) -> io::Result<usize> {
+        let mut frame = Frame(Vec::new());
+        {
+            let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut frame));
+            let mut s: CobsSender = CobsSender::new(&sender);
+
+            if self.options.crc {
+                CrcSender::new(s, Crc32).send(record).map_err(io::Error::other)?;
+            } else {
+                s.send(record).ok_or_else(|| io::Error::other(CobsError::Send))?;
+            }
+        }
+
+        let start = self.end;
+        let end = start + frame.0.len() as u64;
+
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.write_all(&frame.0)?;
+        // A torn record longer than this one would be found again on the next open.
+        if self.tail > frame.0.len() as u64 {
+            self.file.truncate(end)?;
+        }
+        self.tail = 0;
+        self.file.flush()?;
+
+        self.push(start);
+        self.end = end;
+
+        Ok(frame.0.len())
+    }
+
+    pub fn iter(&mut self) -> Records<'_, F> {
+        Records {
+            file: &mut self.file,
+            crc: self.options.crc,
+            position: 0,
+            end: self.end,
+            pending: Vec::new(),
+            seek: true,
+        }
+    }
+
+    /// Iterate from record `n`, starting at the closest indexed record before it.
+    pub fn iter_from(&mut self, n: usize) -> Records<'_, F> {
+        let slot = (n / self.options.index_every).min(self.index.len().saturating_sub(1));
+        let skip = n - slot * self.options.index_every;
+        let position = self.index.get(slot).copied().unwrap_or(self.end);
+
+        let mut records = self.iter();
+        records.position = position;
+        for _ in 0..skip {
+            if records.next().is_none() {
+                break;
+            }
+        }
+
+        records
+    }
+
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+}
+
+pub struct Records<'l, F: Read + Seek> {
+    file: &'l mut F,
+    crc: bool,
+    position: u64,
+    end: u64,
+    pending: Vec<u8>,
+    seek: bool,
+}
+
+impl<F: Read + Seek> Records<'_, F> {
+    fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.seek {
+            self.file.seek(SeekFrom::Start(self.position))?;
+            self.seek = false;
+        }
+
+        let mut buf = [0u8; 4096];
+
+        loop {
+            if let Some(i) = self.pending.iter().position(|b| *b == 0) {
+                let rest = self.pending.split_off(i + 1);
+                return Ok(Some(core::mem::replace(&mut self.pending, rest)));
+            }
+
+            let left = self.end - self.position;
+            if left == 0 {
+                return Ok(None);
+            }
+
+            let n = self.file.read(&mut buf[..left.min(4096) as usize])?;
+            if n == 0 {
+                return Ok(None);
+            }
+
+            self.position += n as u64;
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+impl<F: Read + Seek> Iterator for Records<'_, F> {
+    /// A damaged record yields an `InvalidData` error holding its `CobsError`,
+    /// and iteration goes on with the next one.
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(Some(stored)) => {
+                Some(decode(&stored, self.crc).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                // Report the failure once, then stop.
+                self.end = self.position;
+                self.pending.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Storage of a fixed size, which cannot be cut short.
+    struct Device<'l> {
+        data: Cursor<&'l mut [u8]>,
+        broken: bool,
+    }
+
+    impl Read for Device<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.broken {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+
+            self.data.read(buf)
+        }
+    }
+
+    impl Write for Device<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.data.flush()
+        }
+    }
+
+    impl Seek for Device<'_> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    impl Truncate for Device<'_> {
+        fn truncate(&mut self, _: u64) -> io::Result<()> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    /// The `CobsError` a damaged record is reported with.
+    fn cause(e: io::Error) -> CobsError {
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        *e.into_inner().and_then(|e| e.downcast().ok()).expect("cobs error")
+    }
+
+    fn records() -> Vec<Vec<u8>> {
+        vec![
+            vec![0x11, 0x22, 0x00, 0x33],
+            vec![],
+            vec![0x00],
+            (0..300).map(|i| (i % 251) as u8 + 1).collect(),
+            vec![0x44],
+        ]
+    }
+
+    fn build(options: LogOptions) -> (Vec<u8>, Vec<u64>) {
+        let mut log = CobsLog::open(Cursor::new(Vec::new()), options).expect("open");
+        let mut ends = vec![0];
+
+        for r in records() {
+            let n = log.append(&r).expect("append");
+            ends.push(ends.last().unwrap() + n as u64);
+        }
+
+        (log.into_inner().into_inner(), ends)
+    }
+
+    #[test]
+    fn test_log_truncated_everywhere() {
+        for crc in [false, true] {
+            let options = LogOptions { crc, index_every: 2 };
+            let (data, ends) = build(options);
+
+            for cut in 0..=data.len() {
+                let mut log = CobsLog::open(Cursor::new(data[..cut].to_vec()), options).expect("open");
+                let complete = ends.iter().filter(|e| **e <= cut as u64).count() - 1;
+
+                assert_eq!(log.len(), complete, "cut {}", cut);
+                assert_eq!(log.torn(), cut as u64 - ends[complete], "cut {}", cut);
+
+                let read: Vec<Vec<u8>> = log.iter().map(|r| r.expect("record")).collect();
+                assert_eq!(read, records()[..complete], "cut {}", cut);
+
+                // The next append replaces the torn record.
+                log.append(&[0x55, 0x00]).expect("append");
+                let mut log = CobsLog::open(log.into_inner(), options).expect("open");
+                let mut expected = records()[..complete].to_vec();
+                expected.push(vec![0x55, 0x00]);
+
+                assert_eq!(log.torn(), 0, "cut {}", cut);
+                let read: Vec<Vec<u8>> = log.iter().map(|r| r.expect("record")).collect();
+                assert_eq!(read, expected, "cut {}", cut);
+            }
+        }
+    }
+
+    #[test]
+    fn test_log_long_torn_record() {
+        let options = LogOptions::default();
+        let (data, ends) = build(options);
+
+        // Torn in the middle of the 300-byte record, then a short record is appended.
+        let mut log = CobsLog::open(Cursor::new(data[..ends[3] as usize + 200].to_vec()), options).expect("open");
+        assert_eq!(log.torn(), 200);
+        let n = log.append(&[0x66]).expect("append");
+
+        let file = log.into_inner();
+        assert_eq!(file.get_ref().len(), ends[3] as usize + n);
+
+        let mut log = CobsLog::open(file, options).expect("open");
+        assert_eq!(log.torn(), 0);
+        assert_eq!(log.iter().last().map(|r| r.expect("record")), Some(vec![0x66]));
+    }
+
+    #[test]
+    fn test_log_damaged_record() {
+        let options = LogOptions::default();
+        let (mut data, ends) = build(options);
+
+        // Flip a payload byte of the first record, then break the code chain of the fourth.
+        data[1] ^= 0x01;
+        data[ends[3] as usize] = 0x03;
+
+        let mut log = CobsLog::open(Cursor::new(data), options).expect("open");
+        let read: Vec<Result<Vec<u8>, CobsError>> = log.iter().map(|r| r.map_err(cause)).collect();
+        let expected = records();
+
+        assert_eq!(
+            read,
+            vec![
+                Err(CobsError::Crc),
+                Ok(expected[1].clone()),
+                Ok(expected[2].clone()),
+                Err(CobsError::Recv),
+                Ok(expected[4].clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_iter_from() {
+        let options = LogOptions { crc: false, index_every: 2 };
+        let (data, ends) = build(options);
+        let mut log = CobsLog::open(Cursor::new(data), options).expect("open");
+
+        assert_eq!(log.index, vec![ends[0], ends[2], ends[4]]);
+
+        for n in 0..=records().len() + 1 {
+            let read: Vec<Vec<u8>> = log.iter_from(n).map(|r| r.expect("record")).collect();
+            assert_eq!(read, records()[n.min(records().len())..], "from {}", n);
+        }
+    }
+
+    #[test]
+    fn test_log_io_errors() {
+        let options = LogOptions { crc: false, index_every: 0 };
+        let err = CobsLog::open(Cursor::new(Vec::new()), options).err().expect("index_every");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // The cause of a failed write reaches the caller.
+        let mut file = [0u8; 4];
+        let device = Device { data: Cursor::new(&mut file[..]), broken: false };
+        let mut log = CobsLog::open(device, LogOptions::default()).expect("open");
+        let err = log.append(&[0x11; 8]).expect_err("full");
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+
+        // So does the cause of a failed read, once.
+        let (mut data, _) = build(LogOptions::default());
+        let device = Device { data: Cursor::new(&mut data[..]), broken: false };
+        let mut log = CobsLog::open(device, LogOptions::default()).expect("open");
+        log.file.broken = true;
+        let read: Vec<io::Result<Vec<u8>>> = log.iter().collect();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].as_ref().map_err(|e| e.kind()).err(), Some(io::ErrorKind::BrokenPipe));
+    }
+}
@@ -0,0 +1,219 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Serde format used by `send_message`/`recv_message`: plain postcard.
+//!
+//! The serializer writes through a postcard `Flavor` pushing every byte into
+//! a `BlockEncoder`, so the serialized form is never buffered.
+
+use postcard::ser_flavors::Flavor;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use super::error::CobsError;
+use super::send::{BlockEncoder, CobsSenderOperation};
+
+/// Postcard output stuffing each byte into the frame being sent.
+struct Stuffing<'a> {
+    encoder: &'a mut BlockEncoder,
+    sender: &'a mut dyn CobsSenderOperation,
+    // Set when the sender fails, as opposed to the value refusing to serialize.
+    failed: &'a mut bool,
+}
+
+impl Flavor for Stuffing<'_> {
+    type Output = ();
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.encoder.push(data, self.sender).ok_or_else(|| {
+            *self.failed = true;
+            postcard::Error::SerializeBufferFull
+        })
+    }
+
+    fn finalize(self) -> postcard::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn serialize<T: Serialize + ?Sized>(
+    value: &T,
+    encoder: &mut BlockEncoder,
+    sender: &mut dyn CobsSenderOperation,
+) -> Result<(), CobsError> {
+    let mut failed = false;
+    let flavor = Stuffing { encoder, sender, failed: &mut failed };
+
+    postcard::serialize_with_flavor(value, flavor).map_err(|_| {
+        if failed {
+            CobsError::Send
+        } else {
+            CobsError::Serialize
+        }
+    })
+}
+
+pub(crate) fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, CobsError> {
+    match postcard::take_from_bytes(data) {
+        Ok((value, [])) => Ok(value),
+        _ => Err(CobsError::Deserialize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::{CobsReceiver, CobsReceiverOperation};
+    use crate::testutil::{encode, sent, Mem2Recv};
+    use alloc::collections::BTreeMap;
+    use alloc::rc::Rc;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Command {
+        Ping,
+        Reset(u8),
+        Move { x: i32, y: i32 },
+        Label(String, Option<char>),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Telemetry {
+        id: u16,
+        uptime: u64,
+        temperature: f32,
+        offset: i64,
+        enabled: bool,
+        name: String,
+        samples: Vec<u8>,
+        commands: Vec<Command>,
+        tags: BTreeMap<u8, String>,
+        position: (i8, u32),
+    }
+
+    fn telemetry() -> Telemetry {
+        Telemetry {
+            id: 0x1234,
+            uptime: u64::MAX,
+            temperature: -12.5,
+            offset: i64::MIN,
+            enabled: true,
+            name: String::from("sensor-0"),
+            samples: (0..600).map(|i: u32| (i % 7) as u8).collect(),
+            commands: vec![
+                Command::Ping,
+                Command::Reset(0),
+                Command::Move { x: -1, y: 300 },
+                Command::Label(String::from("ok"), Some('é')),
+            ],
+            tags: BTreeMap::from([(0, String::from("zero")), (0xff, String::new())]),
+            position: (-128, 0),
+        }
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let t = telemetry();
+
+        let encoded = sent(|mut s| {
+            let l = s.send_message(&t).expect("send");
+            s.send_message(&Command::Ping).expect("send");
+            assert_eq!(s.stats().get().1, l + 3);
+        });
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+        assert_eq!(r.recv_message::<Telemetry>(), Ok(t));
+        assert_eq!(r.recv_message::<Command>(), Ok(Command::Ping));
+    }
+
+    #[test]
+    fn test_message_matches_send() {
+        let framed = sent(|mut s| {
+            s.send_message(&(0u8, 300u16, -1i32, "a")).expect("send");
+        });
+
+        assert_eq!(framed, encode(&[[0x00, 0xac, 0x02, 0x01, 0x01, b'a']]));
+    }
+
+    #[test]
+    fn test_message_is_postcard() {
+        let t = telemetry();
+        let mut buf = [0u8; 1024];
+        let bytes = postcard::to_slice(&t, &mut buf).expect("postcard");
+
+        assert_eq!(sent(|mut s| { s.send_message(&t).expect("send"); }), encode(&[&bytes[..]]));
+        assert_eq!(deserialize::<Telemetry>(bytes), Ok(t));
+    }
+
+    #[test]
+    fn test_message_deserialize_error() {
+        let encoded: &[u8] = &[0x03, 0x05, 0xff, 0x00];
+
+        let mut m2r = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+        assert_eq!(r.recv_message::<Command>(), Err(CobsError::Deserialize));
+    }
+
+    #[test]
+    fn test_message_trailing_bytes() {
+        assert_eq!(deserialize::<u8>(&[0x01, 0x02]), Err(CobsError::Deserialize));
+        assert_eq!(deserialize::<u16>(&[0xff, 0xff, 0x04]), Err(CobsError::Deserialize));
+        assert_eq!(deserialize::<u16>(&[0xff, 0xff, 0x03]), Ok(0xffff));
+
+        let mut max = vec![0xff; 18];
+        max.push(0x03);
+        assert_eq!(deserialize::<u128>(&max), Ok(u128::MAX));
+        max[18] = 0x04;
+        assert_eq!(deserialize::<u128>(&max), Err(CobsError::Deserialize));
+    }
+
+    /// Serializes `.0` non-zero bytes, then fails.
+    struct Failing(usize);
+
+    impl Serialize for Failing {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::{Error, SerializeTuple};
+
+            let mut t = serializer.serialize_tuple(self.0 + 1)?;
+            for i in 0..self.0 {
+                t.serialize_element(&((i % 0xff) as u8 + 1))?;
+            }
+
+            Err(S::Error::custom("failing"))
+        }
+    }
+
+    #[test]
+    fn test_message_serialize_error() {
+        for n in [0, 1, 3, 0xfd, 0xfe, 0xff, 300] {
+            let encoded = sent(|mut s| {
+                assert_eq!(s.send_message(&Failing(n)), Err(CobsError::Serialize));
+                assert_eq!(s.stats().get(), (0, 0));
+            });
+
+            assert_eq!(encoded.last(), Some(&0x00));
+            assert!(crate::validate(&encoded).is_err(), "{} bytes", n);
+            assert_eq!(crate::frames(&encoded).collect::<Vec<_>>(), [Err(CobsError::Recv)]);
+
+            // The next message is still received on its own.
+            let encoded = sent(|mut s| {
+                assert!(s.send_message(&Failing(n)).is_err());
+                assert!(s.send_message(&0x1234u16).is_ok());
+            });
+            let mut m2r = Mem2Recv::new(&encoded);
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+            let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+            assert_eq!(r.recv_message::<Vec<u8>>(), Err(CobsError::Recv), "{} bytes", n);
+            assert_eq!(r.recv_message::<u16>(), Ok(0x1234));
+        }
+    }
+}
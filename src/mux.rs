@@ -0,0 +1,194 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Channel multiplexing over a single COBS link.
+//!
+//! Each frame carries `channel || payload` before stuffing.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use super::error::CobsError;
+use super::recv::CobsReceiver;
+use super::send::CobsSender;
+use super::statistics::CobsStatistics;
+
+pub struct CobsMux<'l> {
+    sender: CobsSender<'l>,
+}
+
+impl<'l> CobsMux<'l> {
+    pub fn new(sender: CobsSender<'l>) -> CobsMux<'l> {
+        CobsMux { sender }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.sender.stats()
+    }
+
+    pub fn send(&mut self, channel: u8, buf: &[u8]) -> Result<usize, CobsError> {
+        let mut frame: Vec<u8> = Vec::with_capacity(buf.len() + 1);
+
+        frame.push(channel);
+        frame.extend_from_slice(buf);
+
+        self.sender.send(&frame).ok_or(CobsError::Send)
+    }
+}
+
+type Handler<'h> = Box<dyn FnMut(&[u8]) + 'h>;
+
+enum Channel<'h> {
+    Queue(VecDeque<Vec<u8>>),
+    Handler(Handler<'h>),
+}
+
+pub struct CobsDemux<'l, 'h> {
+    receiver: CobsReceiver<'l>,
+    channels: BTreeMap<u8, Channel<'h>>,
+}
+
+impl<'l, 'h> CobsDemux<'l, 'h> {
+    pub fn new(receiver: CobsReceiver<'l>) -> CobsDemux<'l, 'h> {
+        CobsDemux {
+            receiver,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.receiver.stats()
+    }
+
+    /// Queue the frames received on `channel` until `recv` is called.
+    pub fn open(&mut self, channel: u8) {
+        self.channels.insert(channel, Channel::Queue(VecDeque::new()));
+    }
+
+    /// Hand the frames received on `channel` to `handler` as they are dispatched.
+    pub fn subscribe(&mut self, channel: u8, handler: impl FnMut(&[u8]) + 'h) {
+        self.channels.insert(channel, Channel::Handler(Box::new(handler)));
+    }
+
+    pub fn close(&mut self, channel: u8) {
+        self.channels.remove(&channel);
+    }
+
+    /// Pop the oldest frame queued on `channel`.
+    pub fn recv(&mut self, channel: u8) -> Option<Vec<u8>> {
+        match self.channels.get_mut(&channel) {
+            Some(Channel::Queue(q)) => q.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Receive one frame and route it, returns the channel it was routed to.
+    pub fn dispatch(&mut self) -> Result<u8, CobsError> {
+        let mut data = self.receiver.recv().ok_or(CobsError::Recv)?;
+
+        if data.is_empty() {
+            return Err(CobsError::Recv);
+        }
+
+        let channel = data.remove(0);
+        match self.channels.get_mut(&channel) {
+            Some(Channel::Queue(q)) => q.push_back(data),
+            Some(Channel::Handler(h)) => h(&data),
+            None => {
+                self.receiver.stats_mut().unknown_channel();
+                return Err(CobsError::Channel);
+            }
+        }
+
+        Ok(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiverOperation;
+    use crate::testutil::{sent, Mem2Recv};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    fn encode(frames: &[(u8, &[u8])]) -> Vec<u8> {
+        sent(|sender| {
+            let mut m = CobsMux::new(sender);
+
+            for (channel, f) in frames {
+                m.send(*channel, f).expect("send");
+            }
+        })
+    }
+
+    #[test]
+    fn test_mux_encoding() {
+        let encoded = encode(&[(0x00, &[0x11, 0x22])]);
+
+        assert_eq!(encoded, vec![0x01, 0x03, 0x11, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn test_demux_queues() {
+        let encoded = encode(&[(1, &[0x11]), (2, &[0x22, 0x00]), (1, &[0x33])]);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut d = CobsDemux::new(CobsReceiver::new(&receiver));
+        d.open(1);
+        d.open(2);
+
+        assert_eq!(d.dispatch(), Ok(1));
+        assert_eq!(d.dispatch(), Ok(2));
+        assert_eq!(d.dispatch(), Ok(1));
+
+        assert_eq!(d.recv(1), Some(vec![0x11]));
+        assert_eq!(d.recv(1), Some(vec![0x33]));
+        assert_eq!(d.recv(1), None);
+        assert_eq!(d.recv(2), Some(vec![0x22, 0x00]));
+        assert_eq!(d.recv(3), None);
+    }
+
+    #[test]
+    fn test_demux_handler() {
+        let encoded = encode(&[(7, &[0x11]), (7, &[]), (8, &[0x22])]);
+        let mut logs: Vec<Vec<u8>> = vec![];
+
+        {
+            let mut m2r = Mem2Recv::new(&encoded);
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+            let mut d = CobsDemux::new(CobsReceiver::new(&receiver));
+            d.subscribe(7, |f: &[u8]| logs.push(f.to_vec()));
+            d.open(8);
+
+            assert_eq!(d.dispatch(), Ok(7));
+            assert_eq!(d.dispatch(), Ok(7));
+            assert_eq!(d.dispatch(), Ok(8));
+            assert_eq!(d.recv(8), Some(vec![0x22]));
+        }
+
+        assert_eq!(logs, vec![vec![0x11], vec![]]);
+    }
+
+    #[test]
+    fn test_demux_unknown_channel() {
+        let encoded = encode(&[(1, &[0x11]), (9, &[0x99]), (1, &[0x22])]);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut d = CobsDemux::new(CobsReceiver::new(&receiver));
+        d.open(1);
+
+        assert_eq!(d.dispatch(), Ok(1));
+        assert_eq!(d.dispatch(), Err(CobsError::Channel));
+        assert_eq!(d.dispatch(), Ok(1));
+        assert_eq!(d.stats().unknown_channels(), 1);
+
+        d.close(1);
+        assert_eq!(d.recv(1), None);
+    }
+}
@@ -0,0 +1,355 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Socket transports.
+//!
+//! `TcpStream` and `UnixStream` carry a plain byte stream and are used as is.
+//! `UdpTransport` wraps a connected `UdpSocket`: a frame never spans two
+//! datagrams, and a datagram may carry several frames. A frame cut by the end
+//! of its datagram is reported as a receive error.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use super::recv::CobsReceiverOperation;
+use super::send::CobsSenderOperation;
+
+/// Largest UDP payload over IPv4.
+const DATAGRAM_MAX: usize = 65_507;
+
+fn write_all(mut w: impl Write, buf: &[u8]) -> Option<usize> {
+    w.write_all(buf).ok()?;
+
+    Some(buf.len())
+}
+
+/// Read until `len` bytes arrived, the peer closed or a read timeout expired.
+fn read_up_to(mut r: impl Read, len: usize) -> Option<Vec<u8>> {
+    let mut data = vec![0u8; len];
+    let mut length = 0;
+
+    while length < len {
+        match r.read(&mut data[length..]) {
+            Ok(0) => break,
+            Ok(n) => length += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+
+    if length == 0 {
+        return None;
+    }
+
+    data.truncate(length);
+
+    Some(data)
+}
+
+impl CobsSenderOperation for TcpStream {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        write_all(self, buf)
+    }
+}
+
+impl CobsReceiverOperation for TcpStream {
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        read_up_to(self, len)
+    }
+}
+
+#[cfg(unix)]
+impl CobsSenderOperation for UnixStream {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        write_all(self, buf)
+    }
+}
+
+#[cfg(unix)]
+impl CobsReceiverOperation for UnixStream {
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        read_up_to(self, len)
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    tx: Vec<u8>,
+    rx: VecDeque<u8>,
+    // The current datagram ended in the middle of a frame.
+    cut: bool,
+    batch: bool,
+}
+
+impl UdpTransport {
+    /// Wrap `socket`, which must already be connected to its peer.
+    pub fn new(socket: UdpSocket) -> UdpTransport {
+        UdpTransport {
+            socket,
+            tx: Vec::new(),
+            rx: VecDeque::new(),
+            cut: false,
+            batch: false,
+        }
+    }
+
+    /// Hold complete frames until `flush`, instead of sending one datagram per frame.
+    pub fn set_batch(&mut self, batch: bool) {
+        self.batch = batch;
+    }
+
+    /// Send every complete frame written so far in a single datagram.
+    ///
+    /// On error those frames are dropped, so later ones can still go through.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let end = match self.tx.iter().rposition(|b| *b == 0) {
+            Some(i) => i + 1,
+            None => return Ok(()),
+        };
+        let frames: Vec<u8> = self.tx.drain(..end).collect();
+
+        if frames.len() > DATAGRAM_MAX {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frames do not fit in a datagram"));
+        }
+
+        self.socket.send(&frames)?;
+
+        Ok(())
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+impl CobsSenderOperation for UdpTransport {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.tx.extend_from_slice(buf);
+
+        if !self.batch && buf.contains(&0) {
+            self.flush().ok()?;
+        }
+
+        Some(buf.len())
+    }
+}
+
+impl CobsReceiverOperation for UdpTransport {
+    /// Serve bytes from the current datagram, waiting for the next one only
+    /// once it is exhausted.
+    ///
+    /// The bytes after the last delimiter of a datagram are a cut frame: they
+    /// are dropped and an empty read stands in for them, which `CobsReceiver`
+    /// reports as an error where it expects a code byte.
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.rx.is_empty() && self.cut {
+            self.cut = false;
+            return Some(Vec::new());
+        }
+
+        if self.rx.is_empty() {
+            let mut buf = vec![0u8; DATAGRAM_MAX];
+            let n = loop {
+                match self.socket.recv(&mut buf) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return None,
+                }
+            };
+
+            let end = buf[..n].iter().rposition(|b| *b == 0).map_or(0, |i| i + 1);
+            self.rx.extend(&buf[..end]);
+            self.cut = end < n;
+
+            if self.rx.is_empty() {
+                self.cut = false;
+                return Some(Vec::new());
+            }
+        }
+
+        // Stop a block at the next delimiter, taking it along, so a damaged
+        // frame ends there instead of running into the next one.
+        let length = match self.rx.iter().position(|b| *b == 0) {
+            Some(i) => len.min(i + 1),
+            None => len.min(self.rx.len()),
+        };
+
+        Some(self.rx.drain(..length).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiver;
+    use crate::send::CobsSender;
+    use std::cell::RefCell;
+    use std::net::TcpListener;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn payload() -> Vec<u8> {
+        (0..600).map(|i| (i % 7) as u8).collect()
+    }
+
+    fn send(op: &mut dyn CobsSenderOperation, frames: &[&[u8]]) {
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(op));
+        let mut s: CobsSender = CobsSender::new(&sender);
+
+        for f in frames {
+            assert!(s.send(f).is_some());
+        }
+    }
+
+    fn recv(op: &mut dyn CobsReceiverOperation, count: usize) -> Vec<Vec<u8>> {
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(op));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+        (0..count).map(|_| r.recv().expect("recv")).collect()
+    }
+
+    #[test]
+    fn test_tcp_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("addr")).expect("connect");
+        let (mut server, _) = listener.accept().expect("accept");
+
+        let p = payload();
+        send(&mut client, &[&p, &[0x11, 0x00]]);
+        assert_eq!(recv(&mut server, 2), vec![p, vec![0x11, 0x00]]);
+    }
+
+    #[test]
+    fn test_tcp_partial_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let client = TcpStream::connect(listener.local_addr().expect("addr")).expect("connect");
+        let (mut server, _) = listener.accept().expect("accept");
+
+        let t = thread::spawn(move || {
+            let mut client = client;
+            for b in [0x03, 0x11, 0x22, 0x02, 0x33, 0x00] {
+                client.write_all(&[b]).expect("write");
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        assert_eq!(recv(&mut server, 1), vec![vec![0x11, 0x22, 0x00, 0x33]]);
+        t.join().expect("join");
+
+        // Peer closed: nothing more to read.
+        assert_eq!(CobsReceiverOperation::recv(&mut server, 4), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_loopback() {
+        let (mut a, mut b) = UnixStream::pair().expect("pair");
+
+        let p = payload();
+        send(&mut a, &[&[], &p]);
+        assert_eq!(recv(&mut b, 2), vec![vec![], p.clone()]);
+
+        send(&mut b, &[&p]);
+        assert_eq!(recv(&mut a, 1), vec![p]);
+    }
+
+    fn udp_pair() -> (UdpSocket, UdpSocket) {
+        let a = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let b = UdpSocket::bind("127.0.0.1:0").expect("bind");
+
+        a.connect(b.local_addr().expect("addr")).expect("connect");
+        b.connect(a.local_addr().expect("addr")).expect("connect");
+        b.set_read_timeout(Some(Duration::from_millis(100))).expect("timeout");
+
+        (a, b)
+    }
+
+    #[test]
+    fn test_udp_datagram_per_frame() {
+        let (a, b) = udp_pair();
+        let mut tx = UdpTransport::new(a);
+
+        send(&mut tx, &[&[0x11, 0x00, 0x22], &[0x33]]);
+
+        let mut buf = [0u8; 64];
+        assert_eq!(b.recv(&mut buf).expect("recv"), 5);
+        assert_eq!(buf[..5], [0x02, 0x11, 0x02, 0x22, 0x00]);
+        assert_eq!(b.recv(&mut buf).expect("recv"), 3);
+        assert_eq!(buf[..3], [0x02, 0x33, 0x00]);
+    }
+
+    #[test]
+    fn test_udp_loopback_batched() {
+        let (a, b) = udp_pair();
+        let mut tx = UdpTransport::new(a);
+        let mut rx = UdpTransport::new(b);
+
+        let p = payload();
+        tx.set_batch(true);
+        send(&mut tx, &[&p, &[0x44], &[]]);
+        tx.flush().expect("flush");
+
+        assert_eq!(recv(&mut rx, 3), vec![p, vec![0x44], vec![]]);
+        assert_eq!(CobsReceiverOperation::recv(&mut rx, 1), None);
+    }
+
+    #[test]
+    fn test_udp_truncated_datagram() {
+        let (a, b) = udp_pair();
+        let mut rx = UdpTransport::new(b);
+
+        a.send(&[0x05, 0x11, 0x22]).expect("send");
+        a.send(&[0x02, 0x33, 0x00]).expect("send");
+
+        a.send(&[0x02, 0x44, 0x00, 0x03, 0x55]).expect("send");
+        a.send(&[0x02, 0x66, 0x00]).expect("send");
+
+        // Each cut frame is an error, without touching the next datagram.
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.recv(), Some(vec![0x33]));
+        assert_eq!(r.recv(), Some(vec![0x44]));
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.recv(), Some(vec![0x66]));
+    }
+
+    #[test]
+    fn test_udp_block_past_delimiter() {
+        let (a, b) = udp_pair();
+        let mut rx = UdpTransport::new(b);
+
+        a.send(&[0x05, 0x11, 0x00, 0x02, 0x22, 0x00]).expect("send");
+        a.send(&[0x02, 0x33, 0x04, 0x44, 0x00, 0x02, 0x55, 0x00]).expect("send");
+
+        // A block running past a delimiter ends its frame there, as an error.
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.recv(), Some(vec![0x22]));
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.recv(), Some(vec![0x55]));
+    }
+
+    #[test]
+    fn test_udp_oversized_batch() {
+        let (a, b) = udp_pair();
+        let mut tx = UdpTransport::new(a);
+        let mut rx = UdpTransport::new(b);
+
+        tx.set_batch(true);
+        send(&mut tx, &[&vec![0x42; 70_000]]);
+        assert_eq!(tx.flush().map_err(|e| e.kind()), Err(io::ErrorKind::InvalidInput));
+
+        // The oversized frame is gone, the next one goes through.
+        tx.set_batch(false);
+        send(&mut tx, &[&[0x11]]);
+        assert_eq!(recv(&mut rx, 1), vec![vec![0x11]]);
+    }
+}
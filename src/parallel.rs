@@ -0,0 +1,135 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Parallel encoding of large buffers.
+//!
+//! The sequential encoder always starts a new block right after a zero, and
+//! splits a run without zeros every 254 bytes. Cutting the input at such a
+//! block boundary lets each chunk be encoded on its own; the chunks are then
+//! joined without their end-of-frame bytes, except for the last one.
+
+use rayon::prelude::*;
+
+use super::send::{encode_headroom, stuff_in_place};
+
+const CHUNK: usize = 0xfe * 4096;
+
+fn encode(chunk: &[u8]) -> Vec<u8> {
+    let mut frame = chunk.to_vec();
+
+    // Sized with the headroom, so encoding in place cannot fail.
+    frame.resize(chunk.len() + encode_headroom(chunk.len()), 0);
+    let len = stuff_in_place(&mut frame, chunk.len());
+    frame.truncate(len);
+
+    frame
+}
+
+/// Block boundaries close to every multiple of `chunk`, `chunk` being a multiple of 254.
+///
+/// Cuts are strictly increasing: each one is past the last zero or at least
+/// one full block after the previous cut.
+fn cuts(buf: &[u8], chunk: usize) -> Vec<usize> {
+    let ends: Vec<usize> = (1..buf.len().div_ceil(chunk)).map(|k| k * chunk).collect();
+
+    // The last zero before each nominal end, searched in parallel.
+    let zeros: Vec<Option<usize>> = ends
+        .par_iter()
+        .map(|e| buf[e - chunk..*e].iter().rposition(|b| *b == 0).map(|z| e - chunk + z))
+        .collect();
+
+    let mut cuts = vec![0];
+
+    for (e, z) in ends.into_iter().zip(zeros) {
+        // Without a zero since the previous cut, blocks are full from there.
+        let start = match z {
+            Some(z) => z + 1,
+            None => *cuts.last().unwrap(),
+        };
+
+        cuts.push(start + (e - start) / 0xfe * 0xfe);
+    }
+
+    cuts.push(buf.len());
+
+    cuts
+}
+
+pub(crate) fn encode_chunked(buf: &[u8], chunk: usize) -> Vec<u8> {
+    if buf.is_empty() {
+        return encode(buf);
+    }
+
+    let cuts = cuts(buf, chunk);
+    let last = cuts.len() - 2;
+
+    let frames: Vec<Vec<u8>> = (0..=last)
+        .into_par_iter()
+        .map(|k| {
+            let part = &buf[cuts[k]..cuts[k + 1]];
+            let mut frame = encode(part);
+
+            // Drop what closes the frame: the delimiter, and the empty block
+            // that follows a trailing zero.
+            if k != last {
+                let end = if part.last() == Some(&0) { 2 } else { 1 };
+                frame.truncate(frame.len() - end);
+            }
+
+            frame
+        })
+        .collect();
+
+    frames.concat()
+}
+
+/// Encode `buf` using every rayon worker, delimiter included.
+///
+/// The frame is the same as the one `CobsSender::send` emits.
+pub fn encode_parallel(buf: &[u8]) -> Vec<u8> {
+    encode_chunked(buf, CHUNK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn test_encode_parallel_matches_send() {
+        let mut seed: u32 = 1;
+        let mut random = |zeros: u32| {
+            (0..5000)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    if (seed >> 16) % 1000 < zeros {
+                        0
+                    } else {
+                        (seed >> 24) as u8 | 1
+                    }
+                })
+                .collect::<Vec<u8>>()
+        };
+
+        let mut patterns: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0x00],
+            vec![0x00; 3000],
+            vec![0x42; 3000],
+            (1..=0xfe).chain([0x00]).collect::<Vec<_>>().repeat(20),
+            random(1),
+            random(10),
+            random(500),
+        ];
+        patterns.push([vec![0x42; 1000], vec![0x00], vec![0x42; 2000]].concat());
+
+        for pattern in &patterns {
+            let expected = testutil::encode(&[pattern]);
+
+            for chunk in [0xfe, 0xfe * 2, 0xfe * 7] {
+                assert_eq!(encode_chunked(pattern, chunk), expected, "chunk {}", chunk);
+            }
+            assert_eq!(encode_parallel(pattern), expected);
+        }
+    }
+}
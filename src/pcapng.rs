@@ -0,0 +1,338 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! pcapng export of decoded frames.
+//!
+//! Every frame sent by a `PcapSender` or received by a `PcapReceiver` is
+//! written, once decoded, as an Enhanced Packet Block with a microsecond
+//! timestamp since the Unix epoch and the inbound/outbound direction flag.
+//! The file is little-endian and has a single interface using a user link type
+//! (`LINKTYPE_USER0` by default), so a Wireshark dissector for the payload can
+//! be attached to it.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use super::clock::{CobsClock, SystemClock};
+use super::error::CobsError;
+use super::recv::CobsReceiver;
+use super::send::CobsSender;
+use super::statistics::CobsStatistics;
+
+/// `LINKTYPE_USER0`, first of the user link types 147..=162.
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SHB: u32 = 0x0a0d_0d0a;
+const IDB: u32 = 0x0000_0001;
+const EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const OPT_END: u16 = 0;
+const OPT_EPB_FLAGS: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    /// Bits 0-1 of `epb_flags`.
+    fn flags(self) -> u32 {
+        match self {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        }
+    }
+}
+
+pub struct PcapngWriter<W: Write, C: CobsClock = SystemClock> {
+    out: W,
+    clock: C,
+    packets: usize,
+    error: Option<io::Error>,
+}
+
+fn block(out: &mut impl Write, kind: u32, body: &[u8]) -> io::Result<()> {
+    let length = (12 + body.len()) as u32;
+
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&length.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&length.to_le_bytes())
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(out: W) -> io::Result<PcapngWriter<W>> {
+        PcapngWriter::with_clock(out, LINKTYPE_USER0, SystemClock)
+    }
+}
+
+impl<W: Write, C: CobsClock> PcapngWriter<W, C> {
+    /// Write the section and interface headers for `link_type`, packets are
+    /// then timestamped with `clock`, in microseconds.
+    pub fn with_clock(mut out: W, link_type: u16, clock: C) -> io::Result<PcapngWriter<W, C>> {
+        let mut shb: Vec<u8> = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not specified.
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        block(&mut out, SHB, &shb)?;
+
+        let mut idb: Vec<u8> = Vec::new();
+        idb.extend_from_slice(&link_type.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // No snapshot length limit.
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        block(&mut out, IDB, &idb)?;
+
+        Ok(PcapngWriter {
+            out,
+            clock,
+            packets: 0,
+            error: None,
+        })
+    }
+
+    pub fn packets(&self) -> usize {
+        self.packets
+    }
+
+    pub fn write_packet(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let timestamp = self.clock.now();
+        let padding = (4 - data.len() % 4) % 4;
+        let mut epb: Vec<u8> = Vec::with_capacity(32 + data.len() + padding);
+
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(data);
+        epb.resize(epb.len() + padding, 0);
+
+        epb.extend_from_slice(&OPT_EPB_FLAGS.to_le_bytes());
+        epb.extend_from_slice(&4u16.to_le_bytes());
+        epb.extend_from_slice(&direction.flags().to_le_bytes());
+        epb.extend_from_slice(&OPT_END.to_le_bytes());
+        epb.extend_from_slice(&0u16.to_le_bytes());
+
+        block(&mut self.out, EPB, &epb)?;
+        self.packets += 1;
+
+        Ok(())
+    }
+
+    /// Export a frame on behalf of a wrapper; the first failure is kept for `finish`.
+    fn capture(&mut self, direction: Direction, data: &[u8]) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_packet(direction, data) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Flush the file and give back the writer.
+    ///
+    /// Fails with the first write error met by a `PcapSender` or `PcapReceiver`, if any.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+pub struct PcapSender<'l, W: Write, C: CobsClock = SystemClock> {
+    sender: CobsSender<'l>,
+    writer: Rc<RefCell<PcapngWriter<W, C>>>,
+}
+
+impl<'l, W: Write, C: CobsClock> PcapSender<'l, W, C> {
+    pub fn new(sender: CobsSender<'l>, writer: Rc<RefCell<PcapngWriter<W, C>>>) -> PcapSender<'l, W, C> {
+        PcapSender { sender, writer }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.sender.stats()
+    }
+
+    /// Send `buf` and export it as an outbound packet once it is on the wire.
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize, CobsError> {
+        let total = self.sender.send(buf).ok_or(CobsError::Send)?;
+
+        self.writer.borrow_mut().capture(Direction::Outbound, buf);
+
+        Ok(total)
+    }
+}
+
+pub struct PcapReceiver<'l, W: Write, C: CobsClock = SystemClock> {
+    receiver: CobsReceiver<'l>,
+    writer: Rc<RefCell<PcapngWriter<W, C>>>,
+}
+
+impl<'l, W: Write, C: CobsClock> PcapReceiver<'l, W, C> {
+    pub fn new(receiver: CobsReceiver<'l>, writer: Rc<RefCell<PcapngWriter<W, C>>>) -> PcapReceiver<'l, W, C> {
+        PcapReceiver { receiver, writer }
+    }
+
+    pub fn stats(&self) -> &CobsStatistics {
+        self.receiver.stats()
+    }
+
+    /// Receive a frame and export it as an inbound packet.
+    ///
+    /// The end of the input is `CobsError::Recv` and exports nothing.
+    pub fn recv(&mut self) -> Result<Vec<u8>, CobsError> {
+        let data = self.receiver.recv().ok_or(CobsError::Recv)?;
+
+        self.writer.borrow_mut().capture(Direction::Inbound, &data);
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiverOperation;
+    use crate::testutil::{sent, Mem2Recv};
+    use std::cell::Cell;
+
+    /// Advances 1.5 s on every reading, to exercise the high timestamp word.
+    pub struct TestClock(Cell<u64>);
+
+    impl CobsClock for TestClock {
+        fn now(&self) -> u64 {
+            self.0.set(self.0.get() + 1_500_000);
+            self.0.get()
+        }
+    }
+
+    fn u16_at(b: &[u8], i: usize) -> u16 {
+        u16::from_le_bytes(b[i..i + 2].try_into().unwrap())
+    }
+
+    fn u32_at(b: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes(b[i..i + 4].try_into().unwrap())
+    }
+
+    /// Split a pcapng file into (block type, block body).
+    fn blocks(file: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = vec![];
+        let mut i = 0;
+
+        while i < file.len() {
+            let length = u32_at(file, i + 4) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32_at(file, i + length - 4) as usize, length);
+            blocks.push((u32_at(file, i), file[i + 8..i + length - 4].to_vec()));
+            i += length;
+        }
+
+        blocks
+    }
+
+    fn writer(start: u64) -> PcapngWriter<Vec<u8>, TestClock> {
+        PcapngWriter::with_clock(Vec::new(), LINKTYPE_USER0, TestClock(Cell::new(start))).expect("writer")
+    }
+
+    #[test]
+    fn test_pcapng_wall_clock() {
+        let before = SystemClock.now();
+        let mut w = PcapngWriter::new(Vec::new()).expect("writer");
+        w.write_packet(Direction::Inbound, &[0x11]).expect("packet");
+        let after = SystemClock.now();
+
+        let file = w.finish().expect("finish");
+        let epb = &blocks(&file)[2].1;
+        let timestamp = (u32_at(epb, 4) as u64) << 32 | u32_at(epb, 8) as u64;
+        assert!(before <= timestamp && timestamp <= after);
+    }
+
+    #[test]
+    fn test_pcapng_headers() {
+        let file = writer(0).finish().expect("finish");
+        let blocks = blocks(&file);
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0, SHB);
+        assert_eq!(file[8..12], [0x4d, 0x3c, 0x2b, 0x1a]);
+        assert_eq!((u16_at(&blocks[0].1, 4), u16_at(&blocks[0].1, 6)), (1, 0));
+        assert_eq!(blocks[1].0, IDB);
+        assert_eq!(u16_at(&blocks[1].1, 0), 147);
+    }
+
+    #[test]
+    fn test_pcapng_packets() {
+        let w = Rc::new(RefCell::new(writer(4_294_000_000)));
+        let encoded = sent(|sender| {
+            let mut s = PcapSender::new(sender, w.clone());
+
+            assert_eq!(s.send(&[0x11, 0x00, 0x22]), Ok(5));
+        });
+
+        let capture = [0x02, 0x33, 0x00, 0x06, 0x01, 0x02, 0x03, 0x04, 0x05, 0x00];
+        let mut m2r = Mem2Recv::new(&capture);
+        {
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+            let mut r = PcapReceiver::new(CobsReceiver::new(&receiver), w.clone());
+
+            assert_eq!(r.recv(), Ok(vec![0x33]));
+            assert_eq!(r.recv(), Ok(vec![0x01, 0x02, 0x03, 0x04, 0x05]));
+            assert_eq!(r.stats().get(), (6, 10));
+        }
+
+        assert_eq!(encoded, [0x02, 0x11, 0x02, 0x22, 0x00]);
+        assert_eq!(w.borrow().packets(), 3);
+
+        let file = Rc::try_unwrap(w).ok().expect("unique").into_inner().finish().expect("finish");
+        let packets: Vec<(u64, u32, Vec<u8>)> = blocks(&file)[2..]
+            .iter()
+            .map(|(kind, body)| {
+                assert_eq!(*kind, EPB);
+                assert_eq!(u32_at(body, 0), 0);
+
+                let timestamp = (u32_at(body, 4) as u64) << 32 | u32_at(body, 8) as u64;
+                let length = u32_at(body, 12) as usize;
+                assert_eq!(u32_at(body, 16) as usize, length);
+
+                let opts = 20 + length.div_ceil(4) * 4;
+                assert_eq!((u16_at(body, opts), u16_at(body, opts + 2)), (OPT_EPB_FLAGS, 4));
+                assert_eq!(body[opts + 8..], [0, 0, 0, 0]);
+
+                (timestamp, u32_at(body, opts + 4), body[20..20 + length].to_vec())
+            })
+            .collect();
+
+        assert_eq!(
+            packets,
+            vec![
+                (4_295_500_000, 0b10, vec![0x11, 0x00, 0x22]),
+                (4_297_000_000, 0b01, vec![0x33]),
+                (4_298_500_000, 0b01, vec![0x01, 0x02, 0x03, 0x04, 0x05]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pcapng_idle() {
+        let w = Rc::new(RefCell::new(writer(0)));
+        let mut m2r = Mem2Recv::new(&[0x02, 0x33, 0x00]);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r = PcapReceiver::new(CobsReceiver::new(&receiver), w.clone());
+
+        assert_eq!(r.recv(), Ok(vec![0x33]));
+        assert_eq!(w.borrow().packets(), 1);
+
+        for _ in 0..3 {
+            assert_eq!(r.recv(), Err(CobsError::Recv));
+        }
+        assert_eq!(w.borrow().packets(), 1);
+    }
+}
@@ -0,0 +1,340 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Record and replay of raw link traffic.
+//!
+//! `Recorder` wraps a `CobsReceiverOperation` and logs every read it returns,
+//! `Replay` feeds such a log back to any consumer, whatever lengths it reads.
+//!
+//! File format, integers big-endian:
+//!
+//! ```text
+//! "COBSREC" version(1)
+//! { timestamp_us(u64) length(u32) data[length] }*
+//! ```
+//!
+//! A read that returned `None` is logged with length `0xffffffff` and no data;
+//! a read that long or longer cannot be recorded.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::clock::{CobsClock, MonotonicClock};
+use super::recv::CobsReceiverOperation;
+
+const MAGIC: &[u8; 7] = b"COBSREC";
+const VERSION: u8 = 1;
+const NONE: u32 = u32::MAX;
+
+pub struct Recorder<R: CobsReceiverOperation, W: Write, C: CobsClock = MonotonicClock> {
+    inner: R,
+    out: W,
+    clock: C,
+    error: Option<io::Error>,
+}
+
+impl<R: CobsReceiverOperation, W: Write> Recorder<R, W> {
+    pub fn new(inner: R, out: W) -> io::Result<Recorder<R, W>> {
+        Recorder::with_clock(inner, out, MonotonicClock::default())
+    }
+}
+
+impl<R: CobsReceiverOperation, W: Write, C: CobsClock> Recorder<R, W, C> {
+    /// Record with timestamps taken from `clock`, in microseconds.
+    pub fn with_clock(inner: R, mut out: W, clock: C) -> io::Result<Recorder<R, W, C>> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+
+        Ok(Recorder {
+            inner,
+            out,
+            clock,
+            error: None,
+        })
+    }
+
+    fn log(&mut self, data: Option<&[u8]>) -> io::Result<()> {
+        let length = match data {
+            Some(d) => match u32::try_from(d.len()) {
+                Ok(l) if l != NONE => l,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "read too long to record")),
+            },
+            None => NONE,
+        };
+
+        self.out.write_all(&self.clock.now().to_be_bytes())?;
+        self.out.write_all(&length.to_be_bytes())?;
+        if let Some(d) = data {
+            self.out.write_all(d)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush the log and give back the transport and the writer.
+    ///
+    /// Fails with the first write error met while recording, if any.
+    pub fn finish(mut self) -> io::Result<(R, W)> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        self.out.flush()?;
+
+        Ok((self.inner, self.out))
+    }
+}
+
+impl<R: CobsReceiverOperation, W: Write, C: CobsClock> CobsReceiverOperation for Recorder<R, W, C> {
+    /// Read from the wrapped transport; a failing log never alters the data returned.
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        let data = self.inner.recv(len);
+
+        if self.error.is_none() {
+            if let Err(e) = self.log(data.as_deref()) {
+                self.error = Some(e);
+            }
+        }
+
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    /// Return every read immediately.
+    Fast,
+    /// Wait until each read is due, relative to the first one.
+    RealTime,
+}
+
+pub struct Replay<I: Read> {
+    input: I,
+    pace: Pace,
+    pending: VecDeque<u8>,
+    origin: Option<(Instant, u64)>,
+    reads: usize,
+}
+
+impl<I: Read> Replay<I> {
+    pub fn new(mut input: I, pace: Pace) -> io::Result<Replay<I>> {
+        let mut header = [0u8; 8];
+
+        input.read_exact(&mut header)?;
+        if header[..7] != MAGIC[..] || header[7] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a cobs recording"));
+        }
+
+        Ok(Replay {
+            input,
+            pace,
+            pending: VecDeque::new(),
+            origin: None,
+            reads: 0,
+        })
+    }
+
+    /// Number of recorded reads replayed so far.
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    fn next(&mut self) -> io::Result<(u64, Option<Vec<u8>>)> {
+        let mut timestamp = [0u8; 8];
+        let mut length = [0u8; 4];
+
+        self.input.read_exact(&mut timestamp)?;
+        self.input.read_exact(&mut length)?;
+
+        let timestamp = u64::from_be_bytes(timestamp);
+        let data = match u32::from_be_bytes(length) {
+            NONE => None,
+            l => {
+                // Grow with the bytes actually there, a damaged length must not allocate them up front.
+                let mut data: Vec<u8> = Vec::new();
+                (&mut self.input).take(l as u64).read_to_end(&mut data)?;
+                if data.len() != l as usize {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                Some(data)
+            }
+        };
+
+        Ok((timestamp, data))
+    }
+
+    fn wait(&mut self, timestamp: u64) {
+        let (start, first) = *self.origin.get_or_insert((Instant::now(), timestamp));
+        let due = start + Duration::from_micros(timestamp.saturating_sub(first));
+        let now = Instant::now();
+
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+impl<I: Read> CobsReceiverOperation for Replay<I> {
+    /// Return at most `len` bytes of the recorded traffic, moving to the next
+    /// recorded read once the current one is used up.
+    ///
+    /// Reads are paced on their timestamps; a recorded `None`, an empty read and
+    /// the end of the log are replayed as such.
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            let (timestamp, data) = self.next().ok()?;
+
+            if self.pace == Pace::RealTime {
+                self.wait(timestamp);
+            }
+
+            self.reads += 1;
+            self.pending.extend(data?);
+        }
+
+        let length = len.min(self.pending.len());
+
+        Some(self.pending.drain(..length).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiver;
+    use crate::testutil::Mem2Recv;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    /// Advances 10 ms on every reading.
+    #[derive(Clone)]
+    pub struct TestClock(Rc<Cell<u64>>);
+
+    impl CobsClock for TestClock {
+        fn now(&self) -> u64 {
+            self.0.set(self.0.get() + 10_000);
+            self.0.get()
+        }
+    }
+
+    const CAPTURE: &[u8] = &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x01, 0x00, 0x02, 0x44, 0x00];
+
+    fn record() -> Vec<u8> {
+        let clock = TestClock(Rc::new(Cell::new(0)));
+        let mut recorder = Recorder::with_clock(Mem2Recv::new(CAPTURE), Vec::new(), clock).expect("recorder");
+        {
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut recorder));
+            let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+            assert_eq!(r.recv(), Some(vec![0x11, 0x22, 0x00, 0x33]));
+            assert_eq!(r.recv(), Some(vec![]));
+            assert_eq!(r.recv(), Some(vec![0x44]));
+            // Three frames, then the end of the capture rather than an empty one.
+            assert_eq!(r.recv(), None);
+        }
+
+        let (inner, log) = recorder.finish().expect("finish");
+        assert_eq!(inner.offset, CAPTURE.len());
+
+        log
+    }
+
+    fn replay(log: &[u8], pace: Pace) -> (Vec<Vec<u8>>, (usize, usize), usize) {
+        let mut replay = Replay::new(log, pace).expect("replay");
+        let mut frames: Vec<Vec<u8>> = vec![];
+        let stats;
+        {
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut replay));
+            let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+            while let Some(frame) = r.recv() {
+                frames.push(frame);
+            }
+            stats = r.stats().get();
+        }
+
+        (frames, stats, replay.reads())
+    }
+
+    #[test]
+    fn test_record_format() {
+        let log = record();
+
+        assert_eq!(log[..8], *b"COBSREC\x01");
+        // First read: code byte 0x03 at t = 10 ms.
+        assert_eq!(log[8..21], [0, 0, 0, 0, 0, 0, 0x27, 0x10, 0, 0, 0, 1, 0x03]);
+        // Last read hit the end of the capture.
+        assert_eq!(log[log.len() - 4..], [0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_replay_fast() {
+        let log = record();
+        let (frames, stats, reads) = replay(&log, Pace::Fast);
+
+        assert_eq!(frames, vec![vec![0x11, 0x22, 0x00, 0x33], vec![], vec![0x44]]);
+        assert_eq!(stats, (5, 11));
+        assert_eq!(reads, 11);
+    }
+
+    #[test]
+    fn test_replay_real_time() {
+        let log = record();
+        let start = Instant::now();
+        let (frames, _, reads) = replay(&log, Pace::RealTime);
+
+        // Eleven reads recorded 10 ms apart, the last one finding the end of the capture.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(frames[2], vec![0x44]);
+        assert_eq!(reads, 11);
+    }
+
+    #[test]
+    fn test_replay_other_reads() {
+        // Recorded in 4-byte reads, replayed one byte at a time then frame by frame.
+        let clock = TestClock(Rc::new(Cell::new(0)));
+        let mut recorder = Recorder::with_clock(Mem2Recv::new(CAPTURE), Vec::new(), clock).expect("recorder");
+        while CobsReceiverOperation::recv(&mut recorder, 4).is_some() {}
+        let (_, log) = recorder.finish().expect("finish");
+
+        let mut bytewise = Replay::new(&log[..], Pace::Fast).expect("replay");
+        let mut bytes: Vec<u8> = vec![];
+        while let Some(b) = CobsReceiverOperation::recv(&mut bytewise, 1) {
+            assert_eq!(b.len(), 1);
+            bytes.extend(b);
+        }
+        assert_eq!(bytes, CAPTURE);
+        assert_eq!(bytewise.reads(), 4);
+
+        let (frames, stats, _) = replay(&log, Pace::Fast);
+        assert_eq!(frames, vec![vec![0x11, 0x22, 0x00, 0x33], vec![], vec![0x44]]);
+        assert_eq!(stats, (5, 11));
+    }
+
+    #[test]
+    fn test_replay_truncated() {
+        let log = record();
+        let mut replay = Replay::new(&log[..log.len() - 6], Pace::Fast).expect("replay");
+
+        // The ten complete reads hold the whole capture, the torn last one ends the log.
+        for b in CAPTURE {
+            assert_eq!(CobsReceiverOperation::recv(&mut replay, 1), Some(vec![*b]));
+        }
+        assert_eq!(CobsReceiverOperation::recv(&mut replay, 1), None);
+        assert_eq!(replay.reads(), 10);
+
+        // A damaged length claiming more than the log holds ends it too.
+        let mut log = record();
+        log[16..20].copy_from_slice(&0xffff_fffeu32.to_be_bytes());
+        let mut replay = Replay::new(&log[..], Pace::Fast).expect("replay");
+        assert_eq!(CobsReceiverOperation::recv(&mut replay, 1), None);
+        assert_eq!(replay.reads(), 0);
+
+        assert_eq!(
+            Replay::new(&b"COBSREC\x02"[..], Pace::Fast).err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+}
@@ -6,15 +6,176 @@ extern crate alloc;
 use alloc::{rc::Rc, vec::Vec};
 use core::cell::RefCell;
 
+use super::error::{Anomaly, CobsError};
 use super::statistics::CobsStatistics;
+use super::zero::find_zero;
 
 pub trait CobsReceiverOperation {
     fn recv(&mut self, len: usize) -> Option<Vec<u8>>;
 }
 
+/// Follow the code bytes of the frame at the start of `buf` the way
+/// `CobsReceiver::recv` reads them, and return the offset of its delimiter.
+///
+/// `block` is called with the offset of each code byte, the code byte and the
+/// data bytes following it, as many as `buf` holds. With `scan`, blocks are
+/// searched for zeros: a zero inside a block ends the walk, the data bytes
+/// handed to `block` stopping right before it.
+pub(crate) fn walk(buf: &[u8], scan: bool, mut block: impl FnMut(usize, u8, &[u8])) -> Result<usize, Anomaly> {
+    let mut i = 0;
+
+    loop {
+        let code = match buf.get(i) {
+            Some(0) => return Ok(i),
+            Some(c) => *c as usize,
+            None => return Err(Anomaly::MissingDelimiter { offset: i }),
+        };
+
+        let data = &buf[i + 1..buf.len().min(i + code)];
+        if let Some(z) = find_zero(data).filter(|_| scan) {
+            block(i, code as u8, &data[..z]);
+            return Err(Anomaly::ZeroInBlock { offset: i + 1 + z });
+        }
+
+        block(i, code as u8, data);
+        if data.len() < code - 1 {
+            return Err(Anomaly::TruncatedBlock {
+                offset: i + 1,
+                expected: code - 1,
+                got: data.len(),
+            });
+        }
+
+        i += code;
+    }
+}
+
+/// Decoded length of the frame at the start of `buf`, walked with `scan`.
+fn measure(buf: &[u8], scan: bool) -> Result<usize, Anomaly> {
+    let mut len: usize = 0;
+    let mut last: u8 = 0xff;
+
+    walk(buf, scan, |_, code, data| {
+        if last != 0xff {
+            len += 1;
+        }
+        last = code;
+        len += data.len();
+    })?;
+
+    Ok(len)
+}
+
+/// Check the frame at the start of `buf` and return its decoded length, without copying it.
+///
+/// Code bytes are followed the way `CobsReceiver::recv` reads them, up to the
+/// first delimiter: every block must fit in `buf` and hold no zero.
+pub fn validate(buf: &[u8]) -> Result<usize, Anomaly> {
+    measure(buf, true)
+}
+
+/// Decoded length of the frame at the start of `buf`, as `decode_trusted` writes it.
+///
+/// Only the code bytes are read: every block must fit in `buf` and the frame
+/// must end with a delimiter, but the bytes inside the blocks are not searched
+/// for zeros. Use `validate` for input from an untrusted source.
+pub fn decoded_len(buf: &[u8]) -> Result<usize, Anomaly> {
+    measure(buf, false)
+}
+
+/// Largest payload a frame of `len` bytes, delimiter included, decodes to.
+pub const fn max_decoded_len(len: usize) -> usize {
+    len.saturating_sub(2)
+}
+
+/// Decode the frame held in `buf` over itself and return its decoded length.
+///
+/// The frame must end with a delimiter, the way `validate` checks it.
+pub fn decode_in_place(buf: &mut [u8]) -> Result<usize, CobsError> {
+    let end = walk(buf, true, |_, _, _| ()).map_err(|_| CobsError::Recv)?;
+
+    Ok(unstuff_in_place(buf, end))
+}
+
+/// Decode over itself the checked frame held in `buf[..end]`, delimiter excluded.
+fn unstuff_in_place(buf: &mut [u8], end: usize) -> usize {
+    let mut r = 0;
+    let mut w = 0;
+
+    // The write position never passes the read position: each block shrinks by
+    // its code byte and at most one zero is restored in its place.
+    while r < end {
+        let code = buf[r] as usize;
+
+        buf.copy_within(r + 1..r + code, w);
+        w += code - 1;
+        r += code;
+
+        if code != 0xff && r < end {
+            buf[w] = 0;
+            w += 1;
+        }
+    }
+
+    w
+}
+
+/// Decode the frame at the start of `encoded` into `out` and return its decoded length.
+///
+/// The frame must end with a delimiter, the way `validate` checks it. Blocks
+/// are copied whole once a vectorised search has found the delimiter, which
+/// proves that no block holds a zero.
+pub fn decode(encoded: &[u8], out: &mut [u8]) -> Result<usize, CobsError> {
+    let end = find_zero(encoded).ok_or(CobsError::Recv)?;
+
+    unstuff(&encoded[..end], out)
+}
+
+/// Same as `decode` for input from a known-good source: the bytes inside the
+/// blocks are not searched for zeros, only the code bytes are looked at.
+///
+/// A zero inside a block is copied as data instead of ending the frame; the
+/// frame must still end with a delimiter, the way `decoded_len` checks it.
+pub fn decode_trusted(encoded: &[u8], out: &mut [u8]) -> Result<usize, CobsError> {
+    let end = walk(encoded, false, |_, _, _| ()).map_err(|_| CobsError::Recv)?;
+
+    unstuff(&encoded[..end], out)
+}
+
+/// Decode the code blocks of `encoded` into `out`, following only the code
+/// bytes, up to a zero code byte or the end of `encoded`.
+pub(crate) fn unstuff(encoded: &[u8], out: &mut [u8]) -> Result<usize, CobsError> {
+    let mut r = 0;
+    let mut w = 0;
+
+    while r < encoded.len() && encoded[r] != 0 {
+        let code = encoded[r] as usize;
+        let next = r + code;
+
+        if next > encoded.len() {
+            return Err(CobsError::Recv);
+        }
+
+        out.get_mut(w..w + code - 1)
+            .ok_or(CobsError::Overflow)?
+            .copy_from_slice(&encoded[r + 1..next]);
+        w += code - 1;
+        r = next;
+
+        if code != 0xff && r < encoded.len() && encoded[r] != 0 {
+            *out.get_mut(w).ok_or(CobsError::Overflow)? = 0;
+            w += 1;
+        }
+    }
+
+    Ok(w)
+}
+
 pub struct CobsReceiver<'l> {
     stats: CobsStatistics,
     receiver: &'l Rc<RefCell<&'l mut dyn CobsReceiverOperation>>,
+    // Bytes read past the zero that cut a malformed frame short.
+    pending: Vec<u8>,
 }
 
 impl<'l> CobsReceiver<'l> {
@@ -24,6 +185,7 @@ impl<'l> CobsReceiver<'l> {
         CobsReceiver {
             stats: CobsStatistics::default(),
             receiver,
+            pending: Vec::new(),
         }
     }
 
@@ -31,24 +193,72 @@ impl<'l> CobsReceiver<'l> {
         &self.stats
     }
 
+    pub(crate) fn stats_mut(&mut self) -> &mut CobsStatistics {
+        &mut self.stats
+    }
+
+    /// Read up to `len` bytes, starting with the ones left over by a malformed frame.
+    pub(crate) fn read(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return self.receiver.borrow_mut().recv(len);
+        }
+
+        let mut data: Vec<u8> = self.pending.drain(..len.min(self.pending.len())).collect();
+        if data.len() < len {
+            if let Some(more) = self.receiver.borrow_mut().recv(len - data.len()) {
+                data.extend_from_slice(&more);
+            }
+        }
+
+        Some(data)
+    }
+
+    /// Whether `block` holds a zero; that zero is the delimiter of a malformed
+    /// frame and the bytes following it are kept for the next one.
+    fn cut_at_zero(&mut self, block: &[u8]) -> bool {
+        match find_zero(block) {
+            Some(z) => {
+                self.pending.splice(0..0, block[z + 1..].iter().copied());
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn recv(&mut self) -> Option<Vec<u8>> {
         let mut data: Vec<u8> = Vec::new();
+
+        self.recv_into(&mut data)?;
+
+        Some(data)
+    }
+
+    /// Receive a frame into `data`, replacing its contents but reusing its allocation.
+    ///
+    /// Returns the frame length, or `None` once the input ends; on `None` the
+    /// contents of `data` are unspecified.
+    /// A zero inside a block ends the frame with `None`, the next frame starting
+    /// right after that zero; so does a block read shorter than its code byte says.
+    pub fn recv_into(&mut self, data: &mut Vec<u8>) -> Option<usize> {
+        data.clear();
+
         let mut encoded: usize = 0;
         let mut code: usize = 0xff;
         let mut block: usize = 0x00;
 
         loop {
             if block > 0 {
-                let mut buf = self.receiver.borrow_mut().recv(block)?;
+                let buf = self.read(block)?;
+
+                if self.cut_at_zero(&buf) || buf.len() != block {
+                    return None;
+                }
 
-                data.append(&mut buf);
+                data.extend_from_slice(&buf);
                 encoded += block;
                 block = 0;
             } else {
-                block = match self.receiver.borrow_mut().recv(1) {
-                    Some(mut c) => c.pop()?,
-                    None => 0,
-                } as usize;
+                block = self.read(1)?.pop()? as usize;
 
                 encoded += 1;
 
@@ -67,49 +277,151 @@ impl<'l> CobsReceiver<'l> {
 
         self.stats.update(data.len(), encoded);
 
-        Some(data)
+        Some(data.len())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::cmp::Ordering;
+    /// Receive a frame and write it to `out` block by block, without holding the whole frame.
+    ///
+    /// Returns the frame length. A frame cut short, even right before its
+    /// delimiter, fails with `UnexpectedEof`; bytes of a frame cut short or
+    /// holding a zero inside a block are already written when the error is returned.
+    /// As with `recv`, a zero inside a block is the delimiter of the malformed frame.
+    #[cfg(feature = "std")]
+    pub fn recv_to_writer<W: std::io::Write>(&mut self, mut out: W) -> std::io::Result<usize> {
+        use std::io;
+
+        let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, CobsError::Recv);
+        let mut len: usize = 0;
+        let mut encoded: usize = 0;
+        let mut code: usize = 0xff;
 
-    pub struct Mem2Recv<'l> {
-        pub data: &'l [u8],
-        pub offset: usize,
-    }
+        loop {
+            // Only a real delimiter ends the frame, the end of the input never does.
+            let next = match self.read(1) {
+                Some(c) => *c.first().ok_or_else(truncated)? as usize,
+                None => return Err(truncated()),
+            };
+            encoded += 1;
+
+            if next == 0 {
+                break;
+            }
+
+            if code != 0xff {
+                out.write_all(&[0x00])?;
+                len += 1;
+            }
+            code = next;
+
+            if code > 1 {
+                let block = self.read(code - 1).ok_or_else(truncated)?;
+                if self.cut_at_zero(&block) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, CobsError::Recv));
+                }
+                if block.len() != code - 1 {
+                    return Err(truncated());
+                }
 
-    impl<'l> Mem2Recv<'l> {
-        pub fn new(pattern: &[u8]) -> Mem2Recv {
-            Mem2Recv {
-                data: pattern,
-                offset: 0,
+                out.write_all(&block)?;
+                len += block.len();
+                encoded += block.len();
             }
         }
+
+        self.stats.update(len, encoded);
+
+        Ok(len)
     }
 
-    impl<'l> CobsReceiverOperation for Mem2Recv<'l> {
-        fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
-            let start = self.offset;
+    /// Receive a frame into `buf` and decode it there, without allocating a frame.
+    ///
+    /// `buf` must hold the encoded frame, delimiter excluded. A frame that does
+    /// not fit is read up to its delimiter and dropped with `Err(CobsError::Overflow)`;
+    /// a frame cut short, even right before its delimiter, fails with `Err(CobsError::Recv)`.
+    pub fn recv_in_place(&mut self, buf: &mut [u8]) -> Result<usize, CobsError> {
+        let mut encoded: usize = 0;
+        let mut overflow = false;
 
-            let length = if self.offset + len > self.data.len() {
-                self.data.len() - self.offset
-            } else {
-                len
+        loop {
+            // Only a real delimiter ends the frame, the end of the input never does.
+            let code = match self.read(1) {
+                Some(c) => *c.first().ok_or(CobsError::Recv)?,
+                None => return Err(CobsError::Recv),
+            };
+
+            if code == 0 {
+                break;
+            }
+
+            let block = match code {
+                1 => Vec::new(),
+                _ => self.read(code as usize - 1).ok_or(CobsError::Recv)?,
             };
+            if self.cut_at_zero(&block) || block.len() != code as usize - 1 {
+                return Err(CobsError::Recv);
+            }
 
-            if length == 0 {
-                return None;
+            if encoded + 1 + block.len() > buf.len() {
+                overflow = true;
+            }
+            if !overflow {
+                buf[encoded] = code;
+                buf[encoded + 1..encoded + 1 + block.len()].copy_from_slice(&block);
             }
 
-            self.offset += length;
+            encoded += 1 + block.len();
+        }
 
-            Some(self.data[start..start + length].to_vec())
+        if overflow {
+            return Err(CobsError::Overflow);
+        }
+
+        let len = unstuff_in_place(buf, encoded);
+        self.stats.update(len, encoded + 1);
+
+        Ok(len)
+    }
+
+    /// Receive a frame and deserialize it as a `T`.
+    #[cfg(feature = "serde")]
+    pub fn recv_message<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, CobsError> {
+        let data = self.recv().ok_or(CobsError::Recv)?;
+
+        super::message::deserialize(&data)
+    }
+}
+
+/// Receiver lending each frame from a buffer it reuses, valid until the next call.
+pub struct LendingReceiver<'l> {
+    receiver: CobsReceiver<'l>,
+    frame: Vec<u8>,
+}
+
+impl<'l> LendingReceiver<'l> {
+    pub fn new(receiver: CobsReceiver<'l>) -> LendingReceiver<'l> {
+        LendingReceiver {
+            receiver,
+            frame: Vec::new(),
         }
     }
 
+    pub fn stats(&self) -> &CobsStatistics {
+        self.receiver.stats()
+    }
+
+    pub fn recv(&mut self) -> Option<&[u8]> {
+        self.receiver.recv_into(&mut self.frame)?;
+
+        Some(&self.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::Mem2Recv;
+    use std::cmp::Ordering;
+
     #[test]
     fn test_recv_00() {
         let pattern: Vec<u8> = vec![0x00];
@@ -365,4 +677,276 @@ mod tests {
             None => assert_eq!(false, true),
         }
     }
+
+    #[test]
+    fn test_decode_in_place() {
+        let mut long: Vec<u8> = vec![0xff];
+        long.append(&mut (1..=0xfe).collect::<Vec<_>>());
+        long.append(&mut vec![0x01, 0x01, 0x00, 0x33]);
+
+        let cases: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (vec![0x01, 0x00], vec![]),
+            (vec![0x01, 0x01, 0x01, 0x00], vec![0x00, 0x00]),
+            (vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00], vec![0x11, 0x22, 0x00, 0x33]),
+            (long, (1..=0xfe).chain([0x00]).collect()),
+        ];
+
+        for (mut encoded, pattern) in cases {
+            let len = decode_in_place(&mut encoded).expect("decode");
+            assert_eq!(encoded[..len], pattern[..]);
+        }
+
+        assert_eq!(decode_in_place(&mut [0x03, 0x11, 0x00]), Err(CobsError::Recv));
+        assert_eq!(decode_in_place(&mut [0x02, 0x11, 0x03]), Err(CobsError::Recv));
+    }
+
+    #[test]
+    fn test_recv_in_place() {
+        let encoded: &[u8] = &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x06, 0x01, 0x02, 0x03, 0x04, 0x05, 0x00, 0x01, 0x01, 0x00];
+
+        let mut s2m = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        let mut buf = [0u8; 5];
+
+        assert_eq!(r.recv_in_place(&mut buf), Ok(4));
+        assert_eq!(buf[..4], [0x11, 0x22, 0x00, 0x33]);
+        assert_eq!(r.recv_in_place(&mut buf), Err(CobsError::Overflow));
+        assert_eq!(r.recv_in_place(&mut buf), Ok(1));
+        assert_eq!(buf[0], 0x00);
+        assert_eq!(r.recv_in_place(&mut buf), Err(CobsError::Recv));
+        assert_eq!(r.stats().get(), (5, 9));
+
+        // Neither a frame missing only its delimiter nor one whose last block is cut is a frame.
+        for encoded in [&[0x03, 0x11, 0x22][..], &[0x02, 0x11, 0x04, 0x22]] {
+            let mut s2m = Mem2Recv::new(encoded);
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+            let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+            assert_eq!(r.recv_in_place(&mut buf), Err(CobsError::Recv));
+            assert_eq!(r.stats().get(), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_max_decoded_len() {
+        assert_eq!(max_decoded_len(0), 0);
+        assert_eq!(max_decoded_len(2), 0);
+
+        // Only zeros: every code byte but the first restores one.
+        let mut encoded = [0x01u8; 10];
+        encoded[9] = 0x00;
+        assert_eq!(decode_in_place(&mut encoded), Ok(max_decoded_len(10)));
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut long: Vec<u8> = vec![0xff];
+        long.append(&mut (1..=0xfe).collect::<Vec<_>>());
+        long.append(&mut vec![0x01, 0x01, 0x00, 0x33]);
+
+        let cases: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (vec![0x01, 0x00], vec![]),
+            (vec![0x01, 0x01, 0x01, 0x00], vec![0x00, 0x00]),
+            (vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00], vec![0x11, 0x22, 0x00, 0x33]),
+            (vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x02, 0x44], vec![0x11, 0x22, 0x00, 0x33]),
+            (long, (1..=0xfe).chain([0x00]).collect()),
+        ];
+
+        for (encoded, pattern) in cases {
+            let mut out = vec![0u8; encoded.len()];
+
+            assert_eq!(decode(&encoded, &mut out), Ok(pattern.len()));
+            assert_eq!(out[..pattern.len()], pattern[..]);
+
+            out.fill(0xaa);
+            assert_eq!(decode_trusted(&encoded, &mut out), Ok(pattern.len()));
+            assert_eq!(out[..pattern.len()], pattern[..]);
+
+            if !pattern.is_empty() {
+                assert_eq!(decode(&encoded, &mut out[..pattern.len() - 1]), Err(CobsError::Overflow));
+            }
+        }
+
+        let mut out = [0u8; 8];
+        assert_eq!(decode(&[0x03, 0x11, 0x00], &mut out), Err(CobsError::Recv));
+        assert_eq!(decode(&[0x05, 0x11, 0x22], &mut out), Err(CobsError::Recv));
+        assert_eq!(decode_trusted(&[0x05, 0x11, 0x22], &mut out), Err(CobsError::Recv));
+
+        // Trusted mode does not look inside blocks.
+        assert_eq!(decode_trusted(&[0x03, 0x11, 0x00, 0x00], &mut out), Ok(2));
+        assert_eq!(out[..2], [0x11, 0x00]);
+    }
+
+    #[test]
+    fn test_decode_requires_delimiter() {
+        // Every slice decoder agrees with validate and decoded_len: no delimiter, no frame.
+        let mut out = [0u8; 8];
+
+        for encoded in [&[][..], &[0x03, 0x11, 0x22], &[0x03, 0x11, 0x22, 0x02, 0x33]] {
+            assert!(matches!(validate(encoded), Err(Anomaly::MissingDelimiter { .. })));
+            assert!(matches!(decoded_len(encoded), Err(Anomaly::MissingDelimiter { .. })));
+            assert_eq!(decode(encoded, &mut out), Err(CobsError::Recv));
+            assert_eq!(decode_trusted(encoded, &mut out), Err(CobsError::Recv));
+            assert_eq!(decode_in_place(&mut encoded.to_vec()), Err(CobsError::Recv));
+        }
+
+        let mut encoded = vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00];
+        assert_eq!(validate(&encoded), Ok(4));
+        assert_eq!(decoded_len(&encoded), Ok(4));
+        assert_eq!(decode(&encoded, &mut out), Ok(4));
+        assert_eq!(decode_trusted(&encoded, &mut out), Ok(4));
+        assert_eq!(decode_in_place(&mut encoded), Ok(4));
+    }
+
+    #[test]
+    fn test_recv_into() {
+        let encoded: &[u8] = &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x02, 0x44, 0x00];
+
+        let mut s2m = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        let mut data: Vec<u8> = Vec::with_capacity(16);
+        let ptr = data.as_ptr();
+
+        assert_eq!(r.recv_into(&mut data), Some(4));
+        assert_eq!(data, [0x11, 0x22, 0x00, 0x33]);
+        assert_eq!(r.recv_into(&mut data), Some(1));
+        assert_eq!(data, [0x44]);
+        assert_eq!(data.as_ptr(), ptr);
+        assert_eq!(r.stats().get(), (5, 9));
+
+        // The last block runs past the end of the input.
+        let mut s2m = Mem2Recv::new(&[0x05, 0x11, 0x22]);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        assert_eq!(r.recv_into(&mut data), None);
+    }
+
+    #[test]
+    fn test_recv_after_zero_in_block() {
+        // The zero inside the first block ends it, the next frame is intact.
+        let encoded: &[u8] = &[0x05, 0x11, 0x00, 0x02, 0x22, 0x00, 0x04, 0x33, 0x00, 0x02, 0x44, 0x00];
+
+        let mut s2m = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        let mut buf = [0u8; 8];
+
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.recv(), Some(vec![0x22]));
+        assert_eq!(r.recv_in_place(&mut buf), Err(CobsError::Recv));
+        assert_eq!(r.recv_in_place(&mut buf), Ok(1));
+        assert_eq!(buf[0], 0x44);
+        assert_eq!(r.stats().get(), (2, 6));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_recv_to_writer_after_zero_in_block() {
+        let encoded: &[u8] = &[0x05, 0x11, 0x00, 0x02, 0x22, 0x00];
+
+        let mut s2m = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        let mut out: Vec<u8> = Vec::new();
+
+        let err = r.recv_to_writer(&mut out).expect_err("zero in block");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        out.clear();
+        assert_eq!(r.recv_to_writer(&mut out).expect("frame"), 1);
+        assert_eq!(out, [0x22]);
+    }
+
+    #[test]
+    fn test_lending_receiver() {
+        let encoded: &[u8] = &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x01, 0x00, 0x02, 0x44, 0x00];
+
+        let mut s2m = Mem2Recv::new(encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r = LendingReceiver::new(CobsReceiver::new(&receiver));
+
+        assert_eq!(r.recv(), Some(&[0x11, 0x22, 0x00, 0x33][..]));
+        let ptr = r.frame.as_ptr();
+        assert_eq!(r.recv(), Some(&[][..]));
+        assert_eq!(r.recv(), Some(&[0x44][..]));
+        assert_eq!(r.frame.as_ptr(), ptr);
+
+        // The end of the input is not a frame.
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.stats().get(), (5, 11));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut long: Vec<u8> = vec![0xff];
+        long.append(&mut (1..=0xfe).collect::<Vec<_>>());
+        long.append(&mut vec![0x01, 0x01, 0x00, 0x33]);
+
+        assert_eq!(validate(&[0x00]), Ok(0));
+        assert_eq!(validate(&[0x01, 0x00]), Ok(0));
+        assert_eq!(validate(&[0x01, 0x01, 0x01, 0x00]), Ok(2));
+        assert_eq!(validate(&[0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0x05]), Ok(4));
+        assert_eq!(decoded_len(&long), Ok(255));
+
+        assert_eq!(validate(&[]), Err(Anomaly::MissingDelimiter { offset: 0 }));
+        assert_eq!(validate(&[0x02, 0x11]), Err(Anomaly::MissingDelimiter { offset: 2 }));
+        assert_eq!(validate(&[0x04, 0x11, 0x00, 0x22, 0x00]), Err(Anomaly::ZeroInBlock { offset: 2 }));
+        assert_eq!(
+            decoded_len(&[0x02, 0x11, 0x05, 0x22]),
+            Err(Anomaly::TruncatedBlock { offset: 3, expected: 4, got: 1 })
+        );
+        assert_eq!(decoded_len(&[0x02, 0x11]), Err(Anomaly::MissingDelimiter { offset: 2 }));
+
+        // Only the code bytes are read, the way decode_trusted follows them.
+        let trusted: &[u8] = &[0x04, 0x11, 0x00, 0x22, 0x02, 0x33, 0x00];
+        let mut out = [0u8; 8];
+        assert_eq!(decoded_len(trusted), Ok(5));
+        assert_eq!(decode_trusted(trusted, &mut out), Ok(5));
+
+        // Same length as the receiver decodes.
+        let mut s2m = Mem2Recv::new(&long);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut s2m));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        assert_eq!(r.recv().map(|f| f.len()), Some(255));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_recv_to_writer() {
+        let mut encoded: Vec<u8> = vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00, 0xff];
+        encoded.extend(1..=0xfe);
+        encoded.extend([0x01, 0x01, 0x00, 0x03, 0x44]);
+
+        let mut m2r = Mem2Recv::new(&encoded);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        let mut out: Vec<u8> = Vec::new();
+
+        assert_eq!(r.recv_to_writer(&mut out).expect("frame"), 4);
+        assert_eq!(out, [0x11, 0x22, 0x00, 0x33]);
+
+        out.clear();
+        assert_eq!(r.recv_to_writer(&mut out).expect("frame"), 0xfe + 1);
+        assert_eq!(out, (1..=0xfe).chain([0x00]).collect::<Vec<u8>>());
+        assert_eq!(r.stats().get(), (4 + 0xff, 6 + 0x102));
+
+        // The last block runs past the end of the input.
+        out.clear();
+        let err = r.recv_to_writer(&mut out).expect_err("truncated");
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(r.stats().get(), (4 + 0xff, 6 + 0x102));
+
+        // Neither a frame missing only its delimiter nor an empty input is a frame.
+        for encoded in [&[0x03, 0x11, 0x22][..], &[]] {
+            let mut m2r = Mem2Recv::new(encoded);
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+            let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+            let err = r.recv_to_writer(std::io::sink()).expect_err("no delimiter");
+            assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+            assert_eq!(r.stats().get(), (0, 0));
+        }
+    }
 }
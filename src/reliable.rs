@@ -0,0 +1,630 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Reliable delivery over a COBS link.
+//!
+//! Every frame carries `kind || seq || payload || crc32` before stuffing.
+//! Data frames are acknowledged one by one (selective repeat); a receiver that
+//! sees a gap in the sequence asks for the missing frames with a NAK, and any
+//! frame left unacknowledged for `timeout` ticks of the clock is sent again.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::clock::CobsClock;
+use super::crc::Crc32;
+use super::error::CobsError;
+use super::iter::decode_frame;
+use super::recv::CobsReceiver;
+use super::send::CobsSender;
+use super::zero::find_zero;
+
+const KIND_DATA: u8 = 0x01;
+const KIND_ACK: u8 = 0x02;
+const KIND_NAK: u8 = 0x03;
+
+const HEADER_LEN: usize = 2;
+const CRC_LEN: usize = 4;
+
+/// Largest window that keeps an 8-bit sequence number unambiguous.
+pub const WINDOW_MAX: usize = 128;
+
+struct Pending {
+    frame: Vec<u8>,
+    sent_at: u64,
+    acked: bool,
+}
+
+pub struct ReliableLink<'l, C: CobsClock> {
+    sender: CobsSender<'l>,
+    receiver: CobsReceiver<'l>,
+    clock: C,
+    window: usize,
+    timeout: u64,
+
+    tx_base: u8,
+    tx_pending: VecDeque<Pending>,
+
+    // Bytes of the frame still arriving when the receiver last ran dry.
+    rx_partial: Vec<u8>,
+    rx_base: u8,
+    rx_buffered: VecDeque<Option<Vec<u8>>>,
+    // Slots from `rx_base` whose gaps have already been NAKed.
+    rx_naked: usize,
+    rx_delivered: VecDeque<Vec<u8>>,
+
+    retransmissions: usize,
+    duplicates: usize,
+}
+
+/// Bytes left to read to complete the block under way in `partial`, or the
+/// next code byte when none is.
+fn missing(partial: &[u8]) -> usize {
+    let mut i = 0;
+
+    while i < partial.len() {
+        i += partial[i] as usize;
+    }
+
+    (i - partial.len()).max(1)
+}
+
+fn frame(kind: u8, seq: u8, payload: &[u8]) -> Vec<u8> {
+    let mut f: Vec<u8> = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+
+    f.push(kind);
+    f.push(seq);
+    f.extend_from_slice(payload);
+    let crc = Crc32::checksum(&f);
+    f.extend_from_slice(&crc.to_be_bytes());
+
+    f
+}
+
+impl<'l, C: CobsClock> ReliableLink<'l, C> {
+    /// Fails with `CobsError::Config` unless `window` is within `1..=WINDOW_MAX`.
+    pub fn new(
+        sender: CobsSender<'l>,
+        receiver: CobsReceiver<'l>,
+        clock: C,
+        window: usize,
+        timeout: u64,
+    ) -> Result<ReliableLink<'l, C>, CobsError> {
+        if window == 0 || window > WINDOW_MAX {
+            return Err(CobsError::Config);
+        }
+
+        let mut rx_buffered = VecDeque::with_capacity(window);
+        rx_buffered.resize(window, None);
+
+        Ok(ReliableLink {
+            sender,
+            receiver,
+            clock,
+            window,
+            timeout,
+            tx_base: 0,
+            tx_pending: VecDeque::with_capacity(window),
+            rx_partial: Vec::new(),
+            rx_base: 0,
+            rx_buffered,
+            rx_naked: 0,
+            rx_delivered: VecDeque::new(),
+            retransmissions: 0,
+            duplicates: 0,
+        })
+    }
+
+    pub fn sender(&self) -> &CobsSender<'l> {
+        &self.sender
+    }
+
+    pub fn receiver(&self) -> &CobsReceiver<'l> {
+        &self.receiver
+    }
+
+    /// Number of frames sent but not acknowledged yet.
+    pub fn pending(&self) -> usize {
+        self.tx_pending.iter().filter(|p| !p.acked).count()
+    }
+
+    pub fn retransmissions(&self) -> usize {
+        self.retransmissions
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Queue `buf` for reliable delivery, fails with `CobsError::Busy` when the window is full.
+    pub fn send(&mut self, buf: &[u8]) -> Result<(), CobsError> {
+        if self.tx_pending.len() >= self.window {
+            return Err(CobsError::Busy);
+        }
+
+        let seq = self.tx_base.wrapping_add(self.tx_pending.len() as u8);
+        let f = frame(KIND_DATA, seq, buf);
+
+        self.sender.send(&f).ok_or(CobsError::Send)?;
+        self.tx_pending.push_back(Pending {
+            frame: f,
+            sent_at: self.clock.now(),
+            acked: false,
+        });
+
+        Ok(())
+    }
+
+    /// Process the incoming frames until the receiver runs dry, run the
+    /// retransmission timers and return the next payload delivered in order, if any.
+    ///
+    /// A frame only partly received is kept and completed by the next calls.
+    pub fn poll(&mut self) -> Result<Option<Vec<u8>>, CobsError> {
+        // Never ask for more than the rest of the current block, so that
+        // nothing past a delimiter is read.
+        while let Some(bytes) = self.receiver.read(missing(&self.rx_partial)) {
+            if bytes.is_empty() {
+                break;
+            }
+            self.rx_partial.extend_from_slice(&bytes);
+
+            while let Some(end) = find_zero(&self.rx_partial) {
+                let encoded: Vec<u8> = self.rx_partial.drain(..=end).collect();
+
+                // A malformed frame is dropped, like one failing its CRC.
+                if let Ok(data) = decode_frame(&encoded[..end]) {
+                    self.receiver.stats_mut().update(data.len(), encoded.len());
+                    self.process(&data)?;
+                }
+            }
+        }
+
+        self.retransmit()?;
+
+        Ok(self.rx_delivered.pop_front())
+    }
+
+    fn process(&mut self, data: &[u8]) -> Result<(), CobsError> {
+        if data.len() < HEADER_LEN + CRC_LEN {
+            return Ok(());
+        }
+
+        let end = data.len() - CRC_LEN;
+        if Crc32::checksum(&data[..end]).to_be_bytes() != data[end..] {
+            self.receiver.stats_mut().crc_error();
+            return Ok(());
+        }
+
+        let seq = data[1];
+        match data[0] {
+            KIND_DATA => self.on_data(seq, &data[HEADER_LEN..end]),
+            KIND_ACK => {
+                self.on_ack(seq);
+                Ok(())
+            }
+            KIND_NAK => self.on_nak(seq),
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, seq: u8, payload: &[u8]) -> Result<(), CobsError> {
+        let offset = seq.wrapping_sub(self.rx_base) as usize;
+
+        if offset >= self.window {
+            // Already delivered, the ACK was probably lost: acknowledge it again.
+            if self.rx_base.wrapping_sub(seq) as usize <= self.window {
+                self.duplicates += 1;
+                self.sender.send(&frame(KIND_ACK, seq, &[])).ok_or(CobsError::Send)?;
+            }
+            return Ok(());
+        }
+
+        self.sender.send(&frame(KIND_ACK, seq, &[])).ok_or(CobsError::Send)?;
+
+        if self.rx_buffered[offset].is_some() {
+            self.duplicates += 1;
+            return Ok(());
+        }
+        self.rx_buffered[offset] = Some(payload.to_vec());
+
+        // NAK each gap once, when a later frame first reveals it; a lost NAK
+        // is covered by the sender's timeout.
+        for missing in self.rx_naked..offset {
+            if self.rx_buffered[missing].is_none() {
+                let nak = frame(KIND_NAK, self.rx_base.wrapping_add(missing as u8), &[]);
+                self.sender.send(&nak).ok_or(CobsError::Send)?;
+            }
+        }
+        self.rx_naked = self.rx_naked.max(offset + 1);
+
+        while let Some(slot) = self.rx_buffered.front_mut() {
+            let payload = match slot.take() {
+                Some(p) => p,
+                None => break,
+            };
+
+            self.rx_buffered.pop_front();
+            self.rx_buffered.push_back(None);
+            self.rx_delivered.push_back(payload);
+            self.rx_base = self.rx_base.wrapping_add(1);
+            self.rx_naked -= 1;
+        }
+
+        Ok(())
+    }
+
+    fn on_ack(&mut self, seq: u8) {
+        let offset = seq.wrapping_sub(self.tx_base) as usize;
+
+        if let Some(p) = self.tx_pending.get_mut(offset) {
+            p.acked = true;
+        }
+
+        while let Some(Pending { acked: true, .. }) = self.tx_pending.front() {
+            self.tx_pending.pop_front();
+            self.tx_base = self.tx_base.wrapping_add(1);
+        }
+    }
+
+    fn on_nak(&mut self, seq: u8) -> Result<(), CobsError> {
+        let offset = seq.wrapping_sub(self.tx_base) as usize;
+        let now = self.clock.now();
+
+        if let Some(p) = self.tx_pending.get_mut(offset) {
+            if !p.acked {
+                self.sender.send(&p.frame).ok_or(CobsError::Send)?;
+                p.sent_at = now;
+                self.retransmissions += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn retransmit(&mut self) -> Result<(), CobsError> {
+        let now = self.clock.now();
+
+        for p in self.tx_pending.iter_mut() {
+            if !p.acked && now.wrapping_sub(p.sent_at) >= self.timeout {
+                self.sender.send(&p.frame).ok_or(CobsError::Send)?;
+                p.sent_at = now;
+                self.retransmissions += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recv::CobsReceiverOperation;
+    use crate::send::CobsSenderOperation;
+    use alloc::rc::Rc;
+    use core::cell::{Cell, RefCell};
+
+    #[derive(Clone)]
+    pub struct TestClock(Rc<Cell<u64>>);
+
+    impl CobsClock for TestClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    /// One direction of an in-memory link that drops or corrupts whole frames.
+    pub struct LossyWire {
+        pub frame: Vec<u8>,
+        pub wire: Rc<RefCell<VecDeque<u8>>>,
+        pub seed: u32,
+        pub drop: u32,
+        pub corrupt: u32,
+    }
+
+    impl LossyWire {
+        fn random(&mut self) -> u32 {
+            self.seed = self.seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (self.seed >> 16) % 100
+        }
+    }
+
+    impl CobsSenderOperation for LossyWire {
+        fn send(&mut self, buf: &[u8]) -> Option<usize> {
+            self.frame.extend_from_slice(buf);
+
+            if buf.last() == Some(&0) {
+                let mut f = core::mem::take(&mut self.frame);
+
+                if self.random() < self.drop {
+                    return Some(buf.len());
+                }
+
+                if self.random() < self.corrupt && f.len() > 2 {
+                    let i = f.len() / 2;
+                    f[i] ^= 0x5a;
+                    if f[i] == 0 {
+                        f[i] = 0x5a;
+                    }
+                }
+
+                self.wire.borrow_mut().extend(f);
+            }
+
+            Some(buf.len())
+        }
+    }
+
+    pub struct WireRecv {
+        pub wire: Rc<RefCell<VecDeque<u8>>>,
+    }
+
+    impl CobsReceiverOperation for WireRecv {
+        fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+            let mut wire = self.wire.borrow_mut();
+            let length = len.min(wire.len());
+
+            if length == 0 {
+                return None;
+            }
+
+            Some(wire.drain(..length).collect())
+        }
+    }
+
+    fn wire(seed: u32, drop: u32, corrupt: u32) -> (LossyWire, WireRecv) {
+        let w = Rc::new(RefCell::new(VecDeque::new()));
+
+        (
+            LossyWire {
+                frame: vec![],
+                wire: w.clone(),
+                seed,
+                drop,
+                corrupt,
+            },
+            WireRecv { wire: w },
+        )
+    }
+
+    fn transfer(count: usize, window: usize, drop: u32, corrupt: u32) -> (usize, usize) {
+        let now = Rc::new(Cell::new(0));
+        let (mut a_tx, mut b_rx) = wire(1, drop, corrupt);
+        let (mut b_tx, mut a_rx) = wire(2, drop, corrupt);
+
+        let a_sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut a_tx));
+        let a_receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut a_rx));
+        let b_sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut b_tx));
+        let b_receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut b_rx));
+
+        let mut a = ReliableLink::new(
+            CobsSender::new(&a_sender),
+            CobsReceiver::new(&a_receiver),
+            TestClock(now.clone()),
+            window,
+            50,
+        )
+        .expect("window");
+        let mut b = ReliableLink::new(
+            CobsSender::new(&b_sender),
+            CobsReceiver::new(&b_receiver),
+            TestClock(now.clone()),
+            window,
+            50,
+        )
+        .expect("window");
+
+        let messages: Vec<Vec<u8>> = (0..count)
+            .map(|i| vec![(i % 256) as u8, 0x00, (i / 256) as u8, 0xa5])
+            .collect();
+        let mut sent = 0;
+        let mut received: Vec<Vec<u8>> = vec![];
+
+        for _ in 0..100_000 {
+            if sent < count && a.send(&messages[sent]).is_ok() {
+                sent += 1;
+            }
+
+            assert_eq!(a.poll(), Ok(None));
+            while let Some(p) = b.poll().expect("poll") {
+                received.push(p);
+            }
+
+            if received.len() == count && a.pending() == 0 {
+                break;
+            }
+
+            now.set(now.get() + 10);
+        }
+
+        assert_eq!(received, messages);
+        assert_eq!(a.pending(), 0);
+
+        (a.retransmissions(), b.duplicates())
+    }
+
+    #[test]
+    fn test_reliable_lossless() {
+        let (retransmissions, duplicates) = transfer(300, 8, 0, 0);
+
+        assert_eq!(retransmissions, 0);
+        assert_eq!(duplicates, 0);
+    }
+
+    #[test]
+    fn test_reliable_stop_and_wait() {
+        let (retransmissions, _) = transfer(50, 1, 20, 0);
+
+        assert!(retransmissions > 0);
+    }
+
+    #[test]
+    fn test_reliable_lossy() {
+        let (retransmissions, duplicates) = transfer(300, 16, 20, 0);
+
+        assert!(retransmissions > 0);
+        assert!(duplicates > 0);
+    }
+
+    #[test]
+    fn test_reliable_corrupted() {
+        let (retransmissions, _) = transfer(300, 16, 5, 20);
+
+        assert!(retransmissions > 0);
+    }
+
+    #[test]
+    fn test_reliable_window_full() {
+        let now = Rc::new(Cell::new(0));
+        let (mut tx, mut rx) = wire(1, 100, 0);
+
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut tx));
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+        let mut l = ReliableLink::new(
+            CobsSender::new(&sender),
+            CobsReceiver::new(&receiver),
+            TestClock(now),
+            2,
+            50,
+        )
+        .expect("window");
+
+        assert_eq!(l.send(&[0x11]), Ok(()));
+        assert_eq!(l.send(&[0x22]), Ok(()));
+        assert_eq!(l.send(&[0x33]), Err(CobsError::Busy));
+        assert_eq!(l.pending(), 2);
+    }
+
+    #[test]
+    fn test_reliable_nak_once() {
+        let now = Rc::new(Cell::new(0));
+        let (mut inject, mut rx) = wire(1, 0, 0);
+        let (mut tx, out) = wire(2, 0, 0);
+
+        let inject_sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut inject));
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut tx));
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+        let mut injector = CobsSender::new(&inject_sender);
+        let mut l = ReliableLink::new(
+            CobsSender::new(&sender),
+            CobsReceiver::new(&receiver),
+            TestClock(now),
+            16,
+            50,
+        )
+        .expect("window");
+
+        // Frame 0 is lost, the rest of the window arrives one frame per poll.
+        for seq in 1..16 {
+            injector.send(&frame(KIND_DATA, seq, &[seq])).expect("send");
+            assert_eq!(l.poll(), Ok(None));
+        }
+
+        let replies: Vec<u8> = out.wire.borrow().iter().copied().collect();
+        let naks: Vec<u8> = crate::frames(&replies)
+            .map(|f| f.expect("frame"))
+            .filter(|f| f[0] == KIND_NAK)
+            .map(|f| f[1])
+            .collect();
+        assert_eq!(naks, [0]);
+
+        // Once the window moves on, new gaps are NAKed once each too.
+        injector.send(&frame(KIND_DATA, 0, &[0])).expect("send");
+        injector.send(&frame(KIND_DATA, 18, &[18])).expect("send");
+        injector.send(&frame(KIND_DATA, 19, &[19])).expect("send");
+        for seq in 0..16 {
+            assert_eq!(l.poll(), Ok(Some(vec![seq])));
+        }
+
+        let replies: Vec<u8> = out.wire.borrow().iter().copied().collect();
+        let naks: Vec<u8> = crate::frames(&replies)
+            .map(|f| f.expect("frame"))
+            .filter(|f| f[0] == KIND_NAK)
+            .map(|f| f[1])
+            .collect();
+        assert_eq!(naks, [0, 16, 17]);
+    }
+
+    #[test]
+    fn test_reliable_split_frame() {
+        let now = Rc::new(Cell::new(0));
+        let (_, mut rx) = wire(1, 0, 0);
+        let (mut tx, _) = wire(2, 0, 0);
+        let w = rx.wire.clone();
+
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut tx));
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+        let mut l = ReliableLink::new(
+            CobsSender::new(&sender),
+            CobsReceiver::new(&receiver),
+            TestClock(now),
+            4,
+            50,
+        )
+        .expect("window");
+
+        let first = crate::testutil::encode(&[frame(KIND_DATA, 0, &[0x11, 0x00, 0x22])]);
+        let second = crate::testutil::encode(&[frame(KIND_DATA, 1, &[0x33])]);
+
+        // The link runs dry in the middle of the first frame, then of the second one.
+        w.borrow_mut().extend(&first[..3]);
+        assert_eq!(l.poll(), Ok(None));
+
+        w.borrow_mut().extend(&first[3..]);
+        w.borrow_mut().extend(&second[..3]);
+        assert_eq!(l.poll(), Ok(Some(vec![0x11, 0x00, 0x22])));
+        assert_eq!(l.poll(), Ok(None));
+
+        w.borrow_mut().extend(&second[3..]);
+        assert_eq!(l.poll(), Ok(Some(vec![0x33])));
+        assert_eq!(l.receiver().stats().get().0, 2 * HEADER_LEN + 4 + 2 * CRC_LEN);
+    }
+
+    #[test]
+    fn test_reliable_window_range() {
+        let (mut tx, mut rx) = wire(1, 0, 0);
+
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut tx));
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+
+        for window in [0, WINDOW_MAX + 1] {
+            let clock = TestClock(Rc::new(Cell::new(0)));
+            let l = ReliableLink::new(CobsSender::new(&sender), CobsReceiver::new(&receiver), clock, window, 50);
+
+            assert!(matches!(l, Err(CobsError::Config)), "window {}", window);
+        }
+    }
+
+    #[test]
+    fn test_reliable_poll_dry() {
+        let now = Rc::new(Cell::new(0));
+        let (mut inject, mut rx) = wire(1, 0, 0);
+        let (mut tx, _) = wire(2, 0, 0);
+        let w = rx.wire.clone();
+
+        let inject_sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut inject));
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut tx));
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut rx));
+        let mut injector = CobsSender::new(&inject_sender);
+        let mut l = ReliableLink::new(
+            CobsSender::new(&sender),
+            CobsReceiver::new(&receiver),
+            TestClock(now),
+            4,
+            50,
+        )
+        .expect("window");
+
+        // The receiver runs dry at once: poll returns instead of waiting.
+        assert_eq!(l.poll(), Ok(None));
+
+        // A malformed frame is dropped, the next one is processed by the same poll.
+        w.borrow_mut().extend([0x03, 0x11, 0x00]);
+        injector.send(&frame(KIND_DATA, 0, &[0x42])).expect("send");
+        assert_eq!(l.poll(), Ok(Some(vec![0x42])));
+        assert!(w.borrow().is_empty());
+        assert_eq!(l.poll(), Ok(None));
+    }
+}
@@ -6,12 +6,176 @@ extern crate alloc;
 use alloc::rc::Rc;
 use core::cell::RefCell;
 
+use super::error::CobsError;
 use super::statistics::CobsStatistics;
+use super::zero::find_zero;
 
 pub trait CobsSenderOperation {
     fn send(&mut self, buf: &[u8]) -> Option<usize>;
 }
 
+/// Room needed after a `len` bytes payload to encode it in place, delimiter included.
+///
+/// One code byte per 254 bytes of payload (at least one), plus the delimiter.
+pub const fn encode_headroom(len: usize) -> usize {
+    let blocks = len.div_ceil(0xfe);
+
+    if blocks == 0 {
+        2
+    } else {
+        blocks + 1
+    }
+}
+
+/// Largest frame, delimiter included, `CobsSender::send` emits for a `len` bytes payload.
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + encode_headroom(len)
+}
+
+/// Exact length of the frame, delimiter included, `CobsSender::send` emits for `buf`.
+pub const fn encoded_len(buf: &[u8]) -> usize {
+    let mut total = 0;
+    let mut i = 0;
+
+    loop {
+        let mut code = 0x01;
+
+        while i < buf.len() && buf[i] != 0 && code != 0xff {
+            code += 1;
+            i += 1;
+        }
+
+        total += code;
+
+        if i >= buf.len() {
+            break;
+        }
+
+        if buf[i] == 0 && code < 0xff {
+            i += 1;
+        }
+    }
+
+    total + 1
+}
+
+/// Encode `payload` at compile time into an array of exactly `encoded_len(payload)` bytes.
+///
+/// Meant for constant frames, see `cobs_encode!`; a wrong `M` fails the build.
+pub const fn encode_const<const M: usize>(payload: &[u8]) -> [u8; M] {
+    assert!(M == encoded_len(payload), "array length is not the encoded length");
+
+    let mut frame = [0u8; M];
+    let mut i = 0;
+    let mut w = 0;
+
+    loop {
+        let start = i;
+        let mut code: u8 = 0x01;
+
+        while i < payload.len() && payload[i] != 0 && code != 0xff {
+            frame[w + 1 + i - start] = payload[i];
+            code += 1;
+            i += 1;
+        }
+
+        frame[w] = code;
+        w += code as usize;
+
+        if i >= payload.len() {
+            break;
+        }
+
+        if payload[i] == 0 && code < 0xff {
+            i += 1;
+        }
+    }
+
+    frame
+}
+
+/// Encode a constant payload at compile time into a `&'static [u8]` frame,
+/// delimiter included, identical to what `CobsSender::send` emits.
+///
+/// ```
+/// const PING: &[u8] = libcobs::cobs_encode!(b"ping\0");
+///
+/// assert_eq!(PING, b"\x05ping\x01\x00");
+/// ```
+#[macro_export]
+macro_rules! cobs_encode {
+    ($payload:expr) => {{
+        const PAYLOAD: &[u8] = $payload;
+        const FRAME: [u8; $crate::send::encoded_len(PAYLOAD)] = $crate::send::encode_const(PAYLOAD);
+
+        &FRAME as &'static [u8]
+    }};
+}
+
+/// Encode `payload` into an array sized at compile time, returns the array and
+/// the length of the frame in it.
+///
+/// `M` must be at least `max_encoded_len(N)`, which is checked at compile time:
+/// `encode_to_array::<4, { max_encoded_len(4) }>(&payload)`.
+pub fn encode_to_array<const N: usize, const M: usize>(payload: &[u8; N]) -> ([u8; M], usize) {
+    const { assert!(M >= max_encoded_len(N), "array too small for the encoded payload") };
+
+    let mut buf = [0u8; M];
+    buf[..N].copy_from_slice(payload);
+
+    // The headroom is proven by the assertion above.
+    let len = stuff_in_place(&mut buf, N);
+
+    (buf, len)
+}
+
+/// Encode the `len` bytes payload at the start of `buf` over itself and return
+/// the length of the frame, delimiter included.
+///
+/// `buf` must be at least `len + encode_headroom(len)` long; the frame is the
+/// same as the one `CobsSender::send` emits for that payload.
+pub fn encode_in_place(buf: &mut [u8], len: usize) -> Result<usize, CobsError> {
+    if buf.len() < len + encode_headroom(len) {
+        return Err(CobsError::Overflow);
+    }
+
+    Ok(stuff_in_place(buf, len))
+}
+
+/// `encode_in_place` once `buf` is known to hold the headroom.
+pub(crate) fn stuff_in_place(buf: &mut [u8], len: usize) -> usize {
+    // Move the payload to the end of the buffer, then encode it from there to
+    // the start: the headroom keeps the write position behind the read position.
+    let end = buf.len();
+    let mut i = end - len;
+    let mut w = 0;
+    buf.copy_within(0..len, i);
+
+    loop {
+        let start = i;
+        let limit = (end - i).min(0xfe);
+        let run = find_zero(&buf[i..i + limit]).unwrap_or(limit);
+        let code = run as u8 + 1;
+
+        i += run;
+        buf[w] = code;
+        buf.copy_within(start..i, w + 1);
+        w += code as usize;
+
+        if i >= end {
+            break;
+        }
+
+        if buf[i] == 0 && code < 0xff {
+            i += 1;
+        }
+    }
+
+    buf[w] = 0;
+
+    w + 1
+}
+
 pub struct CobsSender<'l> {
     stats: CobsStatistics,
     sender: &'l Rc<RefCell<&'l mut dyn CobsSenderOperation>>,
@@ -38,25 +202,14 @@ impl<'l> CobsSender<'l> {
         let mut start: usize;
 
         loop {
-            code = 0x01;
             start = i;
 
-            loop {
-                if i >= buf.len() {
-                    break;
-                }
-
-                if buf[i] == 0 {
-                    break;
-                }
-
-                if code == 0xff {
-                    break;
-                }
+            // Longest run of non-zero bytes a block can hold.
+            let limit = (buf.len() - i).min(0xfe);
+            let run = find_zero(&buf[i..i + limit]).unwrap_or(limit);
 
-                code += 1;
-                i += 1;
-            }
+            i += run;
+            code = run as u8 + 1;
 
             self.sender.borrow_mut().send(&[code])?;
 
@@ -84,36 +237,236 @@ impl<'l> CobsSender<'l> {
 
         Some(total)
     }
+
+    /// Encode the bytes of `bytes` as a single frame, holding at most one block at a time.
+    pub fn send_iter<I: IntoIterator<Item = u8>>(&mut self, bytes: I) -> Option<usize> {
+        let mut op = self.sender.borrow_mut();
+        let mut encoder = BlockEncoder::new();
+
+        for byte in bytes {
+            encoder.push(byte, &mut **op)?;
+        }
+
+        let (raw, total) = encoder.finish(&mut **op)?;
+        self.stats.update(raw, total);
+
+        Some(total)
+    }
+
+    /// Encode everything `reader` yields as a single frame, emitting each block as soon as it is complete.
+    ///
+    /// If reading fails halfway the frame ends with a block running past its
+    /// delimiter, so the peer sees a malformed frame rather than a shorter valid one.
+    #[cfg(feature = "std")]
+    pub fn send_from_reader<R: std::io::Read>(&mut self, mut reader: R) -> std::io::Result<usize> {
+        use std::io;
+
+        let failed = || io::Error::other(CobsError::Send);
+        let mut op = self.sender.borrow_mut();
+        let mut encoder = BlockEncoder::new();
+        let mut buf = [0u8; 0x1000];
+
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    encoder.abort(&mut **op).ok_or_else(failed)?;
+                    return Err(e);
+                }
+            };
+
+            for byte in &buf[..n] {
+                encoder.push(*byte, &mut **op).ok_or_else(failed)?;
+            }
+        }
+
+        let (raw, total) = encoder.finish(&mut **op).ok_or_else(failed)?;
+        self.stats.update(raw, total);
+
+        Ok(total)
+    }
+
+    /// Serialize `value` and encode it as a single frame, without buffering the serialized form.
+    ///
+    /// If serialization fails halfway the frame ends with a block running past its
+    /// delimiter, so the peer sees a malformed frame rather than a shorter valid one.
+    #[cfg(feature = "serde")]
+    pub fn send_message<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<usize, CobsError> {
+        let mut op = self.sender.borrow_mut();
+        let mut encoder = BlockEncoder::new();
+
+        if let Err(e) = super::message::serialize(value, &mut encoder, &mut **op) {
+            encoder.abort(&mut **op).ok_or(CobsError::Send)?;
+            return Err(e);
+        }
+
+        let (raw, total) = encoder.finish(&mut **op).ok_or(CobsError::Send)?;
+        self.stats.update(raw, total);
+
+        Ok(total)
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::cmp::Ordering;
+/// Incremental encoder holding at most one pending block.
+///
+/// Bytes are pushed one at a time and every completed block is emitted right
+/// away, so the output matches `CobsSender::send` on the same input.
+pub(crate) struct BlockEncoder {
+    block: [u8; 0xfe],
+    len: usize,
+    full: bool,
+    raw: usize,
+    total: usize,
+}
 
-    pub struct Send2Mem {
-        pub data: Vec<u8>,
+impl BlockEncoder {
+    pub(crate) fn new() -> BlockEncoder {
+        BlockEncoder {
+            block: [0; 0xfe],
+            len: 0,
+            full: false,
+            raw: 0,
+            total: 0,
+        }
     }
 
-    impl Send2Mem {
-        pub fn new() -> Send2Mem {
-            Send2Mem { data: vec![] }
+    fn emit(&mut self, sender: &mut dyn CobsSenderOperation) -> Option<()> {
+        sender.send(&[self.len as u8 + 1])?;
+        if self.len > 0 {
+            sender.send(&self.block[..self.len])?;
+        }
+
+        self.total += self.len + 1;
+        self.full = self.len == self.block.len();
+        self.len = 0;
+
+        Some(())
+    }
+
+    pub(crate) fn push(&mut self, byte: u8, sender: &mut dyn CobsSenderOperation) -> Option<()> {
+        self.raw += 1;
+
+        if byte == 0 {
+            return self.emit(sender);
+        }
+
+        self.block[self.len] = byte;
+        self.len += 1;
+        self.full = false;
+
+        if self.len == self.block.len() {
+            self.emit(sender)?;
         }
 
-        pub fn data(&self) -> &Vec<u8> {
-            &self.data
+        Some(())
+    }
+
+    /// Flush the pending block and the frame delimiter, returns `(raw, encoded)` lengths.
+    pub(crate) fn finish(&mut self, sender: &mut dyn CobsSenderOperation) -> Option<(usize, usize)> {
+        if !(self.full && self.len == 0) {
+            self.emit(sender)?;
         }
+
+        sender.send(&[0])?;
+        self.total += 1;
+
+        Some((self.raw, self.total))
     }
 
-    impl CobsSenderOperation for Send2Mem {
-        fn send(&mut self, buf: &[u8]) -> Option<usize> {
-            for v in buf {
-                self.data.push(*v)
+    /// End the frame so that no decoder accepts it: the pending block goes out
+    /// with a code byte one larger than its data, putting the delimiter inside it.
+    #[cfg(any(feature = "serde", feature = "std"))]
+    pub(crate) fn abort(&mut self, sender: &mut dyn CobsSenderOperation) -> Option<()> {
+        // A pending block is never full, the code byte cannot overflow.
+        sender.send(&[self.len as u8 + 2])?;
+        sender.send(&self.block[..self.len])?;
+        sender.send(&[0])?;
+        self.len = 0;
+
+        Some(())
+    }
+}
+
+pub struct EncodeIter<I> {
+    bytes: I,
+    encoder: BlockEncoder,
+    out: Staged,
+    done: bool,
+}
+
+/// Output of the `BlockEncoder` behind `EncodeIter`, waiting to be yielded.
+///
+/// Sized for the largest emission: a full block with its code byte, then the delimiter.
+struct Staged {
+    buf: [u8; 0x100],
+    len: usize,
+    pos: usize,
+}
+
+impl CobsSenderOperation for Staged {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.buf.get_mut(self.len..self.len + buf.len())?.copy_from_slice(buf);
+        self.len += buf.len();
+
+        Some(buf.len())
+    }
+}
+
+/// Lazily encode `bytes` as a single frame, delimiter included.
+///
+/// Input is pulled one block at a time; the output matches `CobsSender::send`.
+pub fn encode_iter<I: IntoIterator<Item = u8>>(bytes: I) -> EncodeIter<I::IntoIter> {
+    EncodeIter {
+        bytes: bytes.into_iter(),
+        encoder: BlockEncoder::new(),
+        out: Staged {
+            buf: [0; 0x100],
+            len: 0,
+            pos: 0,
+        },
+        done: false,
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for EncodeIter<I> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            if self.out.pos < self.out.len {
+                self.out.pos += 1;
+                return Some(self.out.buf[self.out.pos - 1]);
+            }
+
+            if self.done {
+                return None;
             }
 
-            Some(buf.len())
+            self.out.len = 0;
+            self.out.pos = 0;
+
+            // Pushing a byte emits at most one block, finishing at most one block
+            // and the delimiter: `Staged` holds either.
+            match self.bytes.next() {
+                Some(byte) => self.encoder.push(byte, &mut self.out)?,
+                None => {
+                    self.encoder.finish(&mut self.out)?;
+                    self.done = true;
+                }
+            }
         }
     }
+}
+
+impl<I: Iterator<Item = u8>> core::iter::FusedIterator for EncodeIter<I> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{encode, Send2Mem};
+    use std::cmp::Ordering;
 
     #[test]
     fn test_send_00() {
@@ -131,7 +484,7 @@ mod tests {
                 assert_eq!(enc, encoded.len());
 
                 assert_eq!(l, encoded.len());
-                assert_eq!(s2m.data().cmp(&encoded), Ordering::Equal);
+                assert_eq!(s2m.data.cmp(&encoded), Ordering::Equal);
             }
             None => assert_eq!(false, true),
         }
@@ -374,4 +727,320 @@ mod tests {
             None => assert_eq!(false, true),
         }
     }
+
+    #[test]
+    fn test_block_encoder_matches_send() {
+        let patterns: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0x00],
+            vec![0x11, 0x22, 0x00, 0x33],
+            vec![0x11, 0x00, 0x00, 0x00],
+            (1..=0xfe).collect(),
+            (0..=0xfe).collect(),
+            (1..=0xff).collect(),
+            (1..=0xfe).chain(0..=0x10).collect(),
+            (0..1000).map(|i: u32| (i % 0x100) as u8).collect(),
+        ];
+
+        for pattern in patterns {
+            let mut s2m = Send2Mem::new();
+            {
+                let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+                let mut s: CobsSender = CobsSender::new(&sender);
+                s.send(&pattern).expect("send");
+            }
+
+            let mut block = Send2Mem::new();
+            let mut e = BlockEncoder::new();
+            for b in &pattern {
+                e.push(*b, &mut block).expect("push");
+            }
+            let (raw, enc) = e.finish(&mut block).expect("finish");
+
+            assert_eq!(raw, pattern.len());
+            assert_eq!(enc, s2m.data.len());
+            assert_eq!(block.data, s2m.data);
+        }
+    }
+
+    #[test]
+    fn test_encode_in_place_matches_send() {
+        let mut patterns: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0x00],
+            vec![0x11, 0x22, 0x00, 0x33],
+            vec![0x11, 0x00, 0x00, 0x00],
+            (1..=0xfe).collect(),
+            (0..=0xfe).collect(),
+            (1..=0xff).collect(),
+            (1..=0xfe).chain([0x00]).collect(),
+            (1..=0xfe).chain(0..=0x10).collect(),
+            (0..1000).map(|i: u32| (i % 0x100) as u8).collect(),
+            (0..1000).map(|i: u32| (i % 0xff) as u8 + 1).collect(),
+        ];
+        patterns.push(patterns[7].repeat(3));
+
+        for pattern in patterns {
+            let expected = encode(&[&pattern]);
+            assert!(expected.len() <= pattern.len() + encode_headroom(pattern.len()));
+
+            let mut buf = pattern.clone();
+            buf.resize(pattern.len() + encode_headroom(pattern.len()), 0xaa);
+
+            let len = encode_in_place(&mut buf, pattern.len()).expect("encode");
+            assert_eq!(buf[..len], expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_encode_in_place_headroom() {
+        assert_eq!(encode_headroom(0), 2);
+        assert_eq!(encode_headroom(1), 2);
+        assert_eq!(encode_headroom(254), 2);
+        assert_eq!(encode_headroom(255), 3);
+
+        const HEADROOM: usize = encode_headroom(300);
+        let mut buf = [0x11u8; 300 + HEADROOM];
+        assert_eq!(encode_in_place(&mut buf, 300), Ok(303));
+        assert_eq!(encode_in_place(&mut buf[..301], 300), Err(CobsError::Overflow));
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        let patterns: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0x00],
+            vec![0x11, 0x00, 0x00, 0x00],
+            (1..=0xfe).collect(),
+            (1..=0xfe).chain([0x00]).collect(),
+            (1..=0xfe).chain([0x00]).collect::<Vec<_>>().repeat(3),
+            (0..1000).map(|i: u32| (i % 0xff) as u8 + 1).collect(),
+        ];
+
+        for pattern in patterns {
+            let expected = encode(&[&pattern]);
+
+            assert_eq!(encoded_len(&pattern), expected.len());
+            assert!(expected.len() <= max_encoded_len(pattern.len()));
+        }
+
+        const LEN: usize = encoded_len(&[0x11, 0x22, 0x00, 0x33]);
+        assert_eq!(LEN, 6);
+    }
+
+    #[test]
+    fn test_encode_to_array() {
+        let (buf, len) = encode_to_array::<4, { max_encoded_len(4) }>(&[0x11, 0x22, 0x00, 0x33]);
+        assert_eq!(buf.len(), 6);
+        assert_eq!(buf[..len], [0x03, 0x11, 0x22, 0x02, 0x33, 0x00]);
+
+        let payload = [0x42u8; 600];
+        let (buf, len) = encode_to_array::<600, { max_encoded_len(600) }>(&payload);
+        assert_eq!(buf[..len], encode(&[&payload])[..]);
+    }
+
+    #[test]
+    fn test_cobs_encode_matches_send() {
+        const EMPTY: &[u8] = crate::cobs_encode!(b"");
+        const HELLO: &[u8] = crate::cobs_encode!(b"hello\0world");
+        const ZEROS: &[u8] = crate::cobs_encode!(&[0x00; 3]);
+        const FULL: &[u8] = crate::cobs_encode!(&[0x42; 0xfe]);
+        const LONG: &[u8] = crate::cobs_encode!(&[0x42; 600]);
+
+        assert_eq!(EMPTY, encode(&[b""]));
+        assert_eq!(HELLO, encode(&[b"hello\0world"]));
+        assert_eq!(ZEROS, encode(&[&[0x00; 3]]));
+        assert_eq!(FULL, encode(&[&[0x42; 0xfe]]));
+        assert_eq!(LONG, encode(&[&[0x42; 600]]));
+
+        const FULL_ZERO: &[u8] = crate::cobs_encode!(&{
+            let mut p = [0x42; 0xff];
+            p[0xfe] = 0x00;
+            p
+        });
+        let mut full_zero = [0x42; 0xff];
+        full_zero[0xfe] = 0x00;
+        assert_eq!(FULL_ZERO, encode(&[&full_zero]));
+    }
+
+    fn iter_patterns() -> Vec<Vec<u8>> {
+        vec![
+            vec![],
+            vec![0x00],
+            vec![0x00, 0x00],
+            vec![0x11, 0x22, 0x00, 0x33],
+            (1..=0xfe).collect(),
+            (1..=0xfe).chain([0x00]).collect(),
+            (1..=0xfe).chain([0x00, 0x00]).collect(),
+            (1..=0xff).collect(),
+            (0..1000).map(|i: u32| (i % 0x100) as u8).collect(),
+            vec![0x42; 0xfe * 3],
+        ]
+    }
+
+    #[test]
+    fn test_send_iter_matches_send() {
+        for pattern in iter_patterns() {
+            let expected = encode(&[&pattern]);
+
+            // Split in two slices, as a ring buffer wrapping around would be.
+            let (head, tail) = pattern.split_at(pattern.len() / 3);
+            let mut s2m = Send2Mem::new();
+            {
+                let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+                let mut s: CobsSender = CobsSender::new(&sender);
+
+                assert_eq!(s.send_iter(head.iter().chain(tail).copied()), Some(expected.len()));
+                assert_eq!(s.stats().get(), (pattern.len(), expected.len()));
+            }
+
+            assert_eq!(s2m.data, expected);
+        }
+    }
+
+    #[test]
+    fn test_encode_iter_matches_send() {
+        for pattern in iter_patterns() {
+            let mut it = encode_iter(pattern.iter().copied());
+
+            assert_eq!(it.by_ref().collect::<Vec<u8>>(), encode(&[&pattern]));
+            assert_eq!(it.next(), None);
+        }
+    }
+
+    /// Hands out at most 7 bytes per read, and is interrupted once.
+    #[cfg(feature = "std")]
+    struct Trickle<'l> {
+        data: &'l [u8],
+        interrupted: bool,
+    }
+
+    #[cfg(feature = "std")]
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+
+            let n = buf.len().min(7).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+
+            Ok(n)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_send_from_reader_matches_send() {
+        for pattern in iter_patterns().into_iter().chain([(0..20000).map(|i: u32| (i % 0x1ff) as u8).collect()]) {
+            let expected = encode(&[&pattern]);
+            let mut s2m = Send2Mem::new();
+            {
+                let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+                let mut s: CobsSender = CobsSender::new(&sender);
+
+                assert_eq!(s.send_from_reader(&pattern[..]).expect("send"), expected.len());
+                let reader = Trickle {
+                    data: &pattern,
+                    interrupted: false,
+                };
+                assert_eq!(s.send_from_reader(reader).expect("send"), expected.len());
+                assert_eq!(s.stats().get(), (2 * pattern.len(), 2 * expected.len()));
+            }
+
+            assert_eq!(s2m.data, [expected.clone(), expected].concat());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    struct Failing;
+
+    #[cfg(feature = "std")]
+    impl std::io::Read for Failing {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("boom"))
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_send_from_reader_error() {
+        let reader = std::io::Read::chain(&[0x11u8, 0x22][..], Failing);
+        let mut s2m = Send2Mem::new();
+        {
+            let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+            let mut s: CobsSender = CobsSender::new(&sender);
+
+            assert_eq!(s.send_from_reader(reader).map_err(|e| e.to_string()), Err("boom".to_string()));
+            assert_eq!(s.stats().get(), (0, 0));
+        }
+
+        // The pending block claims one byte more than it holds.
+        assert_eq!(s2m.data, [0x04, 0x11, 0x22, 0x00]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_send_from_reader_error_rejected() {
+        // A full block already on the wire before the failure.
+        let payload: Vec<u8> = (0..300).map(|i| (i % 0xff) as u8 + 1).collect();
+        let reader = std::io::Read::chain(&payload[..], Failing);
+        let mut s2m = Send2Mem::new();
+        {
+            let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+            let mut s: CobsSender = CobsSender::new(&sender);
+
+            assert!(s.send_from_reader(reader).is_err());
+            assert_eq!(s.send(&[0x33]), Some(3));
+        }
+
+        assert!(crate::validate(&s2m.data).is_err());
+        let frames: Vec<Result<Vec<u8>, CobsError>> =
+            crate::frames(&s2m.data).map(|f| f.map(|f| f.into_owned())).collect();
+        assert_eq!(frames, vec![Err(CobsError::Recv), Ok(vec![0x33])]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_send_from_reader_error_recv() {
+        use crate::recv::{CobsReceiver, CobsReceiverOperation};
+        use crate::testutil::Mem2Recv;
+
+        let reader = std::io::Read::chain(&[0x11u8, 0x22][..], Failing);
+        let mut s2m = Send2Mem::new();
+        {
+            let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+            let mut s: CobsSender = CobsSender::new(&sender);
+
+            assert!(s.send_from_reader(reader).is_err());
+            assert_eq!(s.send(&[0x33]), Some(3));
+            assert_eq!(s.send(&[0x44]), Some(3));
+        }
+
+        // The aborted frame is rejected and the following ones are intact.
+        let mut m2r = Mem2Recv::new(&s2m.data);
+        {
+            let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+            let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+            assert_eq!(r.recv(), None);
+            assert_eq!(r.recv(), Some(vec![0x33]));
+
+            let mut out: Vec<u8> = vec![];
+            assert_eq!(r.recv_to_writer(&mut out).expect("recv"), 1);
+            assert_eq!(out, [0x44]);
+        }
+
+        let mut m2r = Mem2Recv::new(&s2m.data);
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut m2r));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+        let mut out: Vec<u8> = vec![];
+        assert_eq!(
+            r.recv_to_writer(&mut out).map_err(|e| e.kind()),
+            Err(std::io::ErrorKind::InvalidData)
+        );
+        assert_eq!(r.recv(), Some(vec![0x33]));
+    }
 }
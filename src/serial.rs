@@ -0,0 +1,343 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Termios serial-port transport (Linux only).
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use super::recv::CobsReceiverOperation;
+use super::send::CobsSenderOperation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud: u32,
+    pub parity: Parity,
+    /// Use two stop bits instead of one.
+    pub two_stop_bits: bool,
+    /// Minimum number of bytes a read waits for (termios `VMIN`).
+    pub vmin: u8,
+    /// Read timeout in tenths of a second (termios `VTIME`).
+    pub vtime: u8,
+}
+
+impl Default for SerialConfig {
+    /// 115200 bauds, 8N1, reads return after 1 s without data.
+    fn default() -> SerialConfig {
+        SerialConfig {
+            baud: 115_200,
+            parity: Parity::None,
+            two_stop_bits: false,
+            vmin: 0,
+            vtime: 10,
+        }
+    }
+}
+
+fn speed(baud: u32) -> io::Result<libc::speed_t> {
+    Ok(match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19_200 => libc::B19200,
+        38_400 => libc::B38400,
+        57_600 => libc::B57600,
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        460_800 => libc::B460800,
+        500_000 => libc::B500000,
+        576_000 => libc::B576000,
+        921_600 => libc::B921600,
+        1_000_000 => libc::B1000000,
+        1_500_000 => libc::B1500000,
+        2_000_000 => libc::B2000000,
+        3_000_000 => libc::B3000000,
+        4_000_000 => libc::B4000000,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported baud rate {}", baud),
+            ))
+        }
+    })
+}
+
+/// Raw mode plus the framing, timeouts and speed requested by `config`.
+fn apply(tio: &mut libc::termios, config: &SerialConfig) -> io::Result<()> {
+    let speed = speed(config.baud)?;
+
+    // SAFETY: `tio` is a valid termios borrowed mutably.
+    unsafe { libc::cfmakeraw(tio) };
+
+    tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+    tio.c_cflag &= !(libc::PARENB | libc::PARODD | libc::CSTOPB);
+    tio.c_iflag &= !libc::INPCK;
+    match config.parity {
+        Parity::None => {}
+        Parity::Even => {
+            tio.c_cflag |= libc::PARENB;
+            tio.c_iflag |= libc::INPCK;
+        }
+        Parity::Odd => {
+            tio.c_cflag |= libc::PARENB | libc::PARODD;
+            tio.c_iflag |= libc::INPCK;
+        }
+    }
+    if config.two_stop_bits {
+        tio.c_cflag |= libc::CSTOPB;
+    }
+
+    tio.c_cc[libc::VMIN] = config.vmin;
+    tio.c_cc[libc::VTIME] = config.vtime;
+
+    // SAFETY: `tio` is a valid termios borrowed mutably; a bad speed is reported through errno.
+    if unsafe { libc::cfsetispeed(tio, speed) } != 0 || unsafe { libc::cfsetospeed(tio, speed) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+pub struct SerialPort {
+    file: File,
+}
+
+impl SerialPort {
+    pub fn open(path: &Path, config: &SerialConfig) -> io::Result<SerialPort> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+
+        SerialPort::from_file(file, config)
+    }
+
+    /// Wrap an already opened terminal, e.g. one side of a pseudo-terminal.
+    pub fn from_file(file: File, config: &SerialConfig) -> io::Result<SerialPort> {
+        let mut port = SerialPort { file };
+
+        port.configure(config)?;
+
+        Ok(port)
+    }
+
+    pub fn configure(&mut self, config: &SerialConfig) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        // SAFETY: termios is plain old data, all zeroes is a valid value.
+        let mut tio: libc::termios = unsafe { core::mem::zeroed() };
+
+        // SAFETY: `fd` stays open as long as `self.file`, `tio` is valid for writes.
+        if unsafe { libc::tcgetattr(fd, &mut tio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        apply(&mut tio, config)?;
+
+        // SAFETY: `fd` stays open as long as `self.file`, `tio` is only read.
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &tio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Read back the settings currently applied to the terminal.
+    pub fn config(&self) -> io::Result<SerialConfig> {
+        // SAFETY: termios is plain old data, all zeroes is a valid value.
+        let mut tio: libc::termios = unsafe { core::mem::zeroed() };
+
+        // SAFETY: the fd stays open as long as `self.file`, `tio` is valid for writes.
+        if unsafe { libc::tcgetattr(self.file.as_raw_fd(), &mut tio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: `tio` was filled in by tcgetattr and is only read.
+        let ispeed = unsafe { libc::cfgetispeed(&tio) };
+        let baud = [
+            1200, 2400, 4800, 9600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 500_000, 576_000, 921_600,
+            1_000_000, 1_500_000, 2_000_000, 3_000_000, 4_000_000,
+        ]
+        .into_iter()
+        .find(|b| speed(*b).ok() == Some(ispeed))
+        .unwrap_or(0);
+
+        let parity = match (tio.c_cflag & libc::PARENB != 0, tio.c_cflag & libc::PARODD != 0) {
+            (false, _) => Parity::None,
+            (true, false) => Parity::Even,
+            (true, true) => Parity::Odd,
+        };
+
+        Ok(SerialConfig {
+            baud,
+            parity,
+            two_stop_bits: tio.c_cflag & libc::CSTOPB != 0,
+            vmin: tio.c_cc[libc::VMIN],
+            vtime: tio.c_cc[libc::VTIME],
+        })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl CobsSenderOperation for SerialPort {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.file.write_all(buf).ok()?;
+
+        Some(buf.len())
+    }
+}
+
+impl CobsReceiverOperation for SerialPort {
+    /// Read up to `len` bytes, returns fewer when the `VTIME` timeout expires
+    /// and `None` when nothing arrived at all.
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        let mut data = vec![0u8; len];
+        let mut length = 0;
+
+        while length < len {
+            match self.file.read(&mut data[length..]) {
+                Ok(0) => break,
+                Ok(n) => length += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+
+        if length == 0 {
+            return None;
+        }
+
+        data.truncate(length);
+
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::Pty;
+    use crate::recv::CobsReceiver;
+    use crate::send::CobsSender;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_serial_parity() {
+        // SAFETY: termios is plain old data, all zeroes is a valid value.
+        let mut tio: libc::termios = unsafe { core::mem::zeroed() };
+
+        let odd = SerialConfig {
+            parity: Parity::Odd,
+            ..SerialConfig::default()
+        };
+        apply(&mut tio, &odd).expect("apply");
+        assert_eq!(tio.c_cflag & (libc::PARENB | libc::PARODD), libc::PARENB | libc::PARODD);
+        assert_ne!(tio.c_iflag & libc::INPCK, 0);
+
+        let even = SerialConfig {
+            parity: Parity::Even,
+            ..SerialConfig::default()
+        };
+        apply(&mut tio, &even).expect("apply");
+        assert_eq!(tio.c_cflag & (libc::PARENB | libc::PARODD), libc::PARENB);
+
+        apply(&mut tio, &SerialConfig::default()).expect("apply");
+        assert_eq!(tio.c_cflag & (libc::PARENB | libc::PARODD | libc::CSTOPB), 0);
+        assert_eq!(tio.c_iflag & libc::INPCK, 0);
+        assert_eq!(tio.c_cflag & libc::CSIZE, libc::CS8);
+    }
+
+    #[test]
+    fn test_serial_config() {
+        // Pseudo-terminals ignore parity, it is covered by test_serial_parity.
+        let pty = Pty::open().expect("pty");
+        let config = SerialConfig {
+            baud: 9600,
+            parity: Parity::None,
+            two_stop_bits: true,
+            vmin: 1,
+            vtime: 5,
+        };
+
+        let mut port = SerialPort::open(pty.path(), &config).expect("open");
+        assert_eq!(port.config().expect("config"), config);
+
+        port.configure(&SerialConfig::default()).expect("configure");
+        assert_eq!(port.config().expect("config"), SerialConfig::default());
+
+        let config = SerialConfig {
+            baud: 12_345,
+            ..SerialConfig::default()
+        };
+        assert_eq!(
+            port.configure(&config).map_err(|e| e.kind()),
+            Err(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_serial_send() {
+        let pty = Pty::open().expect("pty");
+        let mut port = SerialPort::open(pty.path(), &SerialConfig::default()).expect("open");
+        {
+            let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut port));
+            let mut s: CobsSender = CobsSender::new(&sender);
+            assert_eq!(s.send(&[0x11, 0x22, 0x00, 0x33]), Some(6));
+        }
+
+        let mut data = [0u8; 6];
+        pty.master().read_exact(&mut data).expect("read");
+        assert_eq!(data, [0x03, 0x11, 0x22, 0x02, 0x33, 0x00]);
+    }
+
+    #[test]
+    fn test_serial_recv() {
+        let pty = Pty::open().expect("pty");
+        let config = SerialConfig {
+            vtime: 1,
+            ..SerialConfig::default()
+        };
+        let mut port = SerialPort::open(pty.path(), &config).expect("open");
+
+        pty.master()
+            .write_all(&[0x02, 0x11, 0x01, 0x00, 0x05, 0x11, 0x22, 0x33, 0x44, 0x00])
+            .expect("write");
+
+        let receiver: Rc<RefCell<&mut dyn CobsReceiverOperation>> = Rc::new(RefCell::new(&mut port));
+        let mut r: CobsReceiver = CobsReceiver::new(&receiver);
+
+        assert_eq!(r.recv(), Some(vec![0x11, 0x00]));
+        assert_eq!(r.recv(), Some(vec![0x11, 0x22, 0x33, 0x44]));
+        assert_eq!(r.stats().get(), (6, 10));
+    }
+
+    #[test]
+    fn test_serial_timeout() {
+        let pty = Pty::open().expect("pty");
+        let config = SerialConfig {
+            vtime: 1,
+            ..SerialConfig::default()
+        };
+        let mut port = SerialPort::open(pty.path(), &config).expect("open");
+
+        assert_eq!(CobsReceiverOperation::recv(&mut port, 4), None);
+
+        pty.master().write_all(&[0x11, 0x22]).expect("write");
+        assert_eq!(CobsReceiverOperation::recv(&mut port, 4), Some(vec![0x11, 0x22]));
+    }
+}
@@ -5,6 +5,8 @@
 pub struct CobsStatistics {
     raw: usize,
     encoded: usize,
+    crc_errors: usize,
+    unknown_channels: usize,
 }
 
 impl CobsStatistics {
@@ -16,6 +18,22 @@ impl CobsStatistics {
     pub fn get(&self) -> (usize, usize) {
         (self.raw, self.encoded)
     }
+
+    pub fn crc_error(&mut self) {
+        self.crc_errors += 1;
+    }
+
+    pub fn crc_errors(&self) -> usize {
+        self.crc_errors
+    }
+
+    pub fn unknown_channel(&mut self) {
+        self.unknown_channels += 1;
+    }
+
+    pub fn unknown_channels(&self) -> usize {
+        self.unknown_channels
+    }
 }
 
 #[cfg(test)]
@@ -36,4 +54,24 @@ mod tests {
         s.update(1, 1);
         assert_eq!(s.get(), (4, 4));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stat_crc_error() {
+        let mut s = CobsStatistics::default();
+
+        assert_eq!(s.crc_errors(), 0);
+        s.crc_error();
+        s.crc_error();
+        assert_eq!(s.crc_errors(), 2);
+        assert_eq!(s.get(), (0, 0));
+    }
+
+    #[test]
+    fn test_stat_unknown_channel() {
+        let mut s = CobsStatistics::default();
+
+        s.unknown_channel();
+        assert_eq!(s.unknown_channels(), 1);
+        assert_eq!(s.crc_errors(), 0);
+    }
+}
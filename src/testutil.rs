@@ -0,0 +1,82 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! In-memory transports shared by the unit tests.
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use super::recv::CobsReceiverOperation;
+use super::send::{CobsSender, CobsSenderOperation};
+
+pub(crate) struct Send2Mem {
+    pub data: Vec<u8>,
+}
+
+impl Send2Mem {
+    pub fn new() -> Send2Mem {
+        Send2Mem { data: Vec::new() }
+    }
+}
+
+impl CobsSenderOperation for Send2Mem {
+    fn send(&mut self, buf: &[u8]) -> Option<usize> {
+        self.data.extend_from_slice(buf);
+
+        Some(buf.len())
+    }
+}
+
+/// Hands out `data` in reads of at most `chunk` bytes.
+pub(crate) struct Mem2Recv<'l> {
+    pub data: &'l [u8],
+    pub offset: usize,
+    pub chunk: usize,
+}
+
+impl<'l> Mem2Recv<'l> {
+    pub fn new(data: &'l [u8]) -> Mem2Recv<'l> {
+        Mem2Recv::chunked(data, usize::MAX)
+    }
+
+    pub fn chunked(data: &'l [u8], chunk: usize) -> Mem2Recv<'l> {
+        Mem2Recv { data, offset: 0, chunk }
+    }
+}
+
+impl CobsReceiverOperation for Mem2Recv<'_> {
+    fn recv(&mut self, len: usize) -> Option<Vec<u8>> {
+        let length = len.min(self.chunk).min(self.data.len() - self.offset);
+
+        if length == 0 {
+            return None;
+        }
+
+        self.offset += length;
+
+        Some(self.data[self.offset - length..self.offset].to_vec())
+    }
+}
+
+/// Everything `f` sends through a `CobsSender` writing to memory.
+pub(crate) fn sent(f: impl FnOnce(CobsSender<'_>)) -> Vec<u8> {
+    let mut s2m = Send2Mem::new();
+    {
+        let sender: Rc<RefCell<&mut dyn CobsSenderOperation>> = Rc::new(RefCell::new(&mut s2m));
+        f(CobsSender::new(&sender));
+    }
+
+    s2m.data
+}
+
+/// Encode each payload as one frame.
+pub(crate) fn encode<P: AsRef<[u8]>>(payloads: &[P]) -> Vec<u8> {
+    sent(|mut s| {
+        for p in payloads {
+            s.send(p.as_ref()).expect("send");
+        }
+    })
+}
@@ -0,0 +1,111 @@
+// SPDX Licence-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: 2024 Laurent Fazio <laurent.fazio@gmail.com>
+
+//! Zero byte search, a SIMD vector or a machine word at a time.
+
+const LO: u64 = 0x0101_0101_0101_0101;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+/// Position of the first zero in `buf`.
+#[inline]
+pub(crate) fn find_zero(buf: &[u8]) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        find_zero_sse2(buf)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        find_zero_word(buf)
+    }
+}
+
+/// Portable version, eight bytes at a time.
+#[inline]
+pub(crate) fn find_zero_word(buf: &[u8]) -> Option<usize> {
+    let mut i = 0;
+
+    while i + 8 <= buf.len() {
+        let w = u64::from_le_bytes([
+            buf[i],
+            buf[i + 1],
+            buf[i + 2],
+            buf[i + 3],
+            buf[i + 4],
+            buf[i + 5],
+            buf[i + 6],
+            buf[i + 7],
+        ]);
+
+        // The lowest bit set is in the first zero byte; bytes after it may
+        // report false positives, which does not matter.
+        let t = w.wrapping_sub(LO) & !w & HI;
+        if t != 0 {
+            return Some(i + (t.trailing_zeros() / 8) as usize);
+        }
+
+        i += 8;
+    }
+
+    buf[i..].iter().position(|b| *b == 0).map(|j| i + j)
+}
+
+/// SSE2 is part of the x86_64 baseline, no runtime detection needed.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub(crate) fn find_zero_sse2(buf: &[u8]) -> Option<usize> {
+    use core::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_setzero_si128};
+
+    let mut i = 0;
+
+    while i + 16 <= buf.len() {
+        // SAFETY: 16 bytes are readable at `i`, the load is unaligned.
+        let mask = unsafe {
+            let v = _mm_loadu_si128(buf.as_ptr().add(i) as *const __m128i);
+            _mm_movemask_epi8(_mm_cmpeq_epi8(v, _mm_setzero_si128()))
+        };
+
+        if mask != 0 {
+            return Some(i + mask.trailing_zeros() as usize);
+        }
+
+        i += 16;
+    }
+
+    find_zero_word(&buf[i..]).map(|j| i + j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_zero() {
+        let mut buf = [0x80u8; 100];
+
+        assert_eq!(find_zero(&buf), None);
+        assert_eq!(find_zero_word(&buf), None);
+        assert_eq!(find_zero(&[]), None);
+
+        for start in 0..20 {
+            for zero in start..buf.len() {
+                buf[zero] = 0x00;
+                // A 0x01 right after the zero is the classic false positive.
+                if zero + 1 < buf.len() {
+                    buf[zero + 1] = 0x01;
+                }
+
+                let expected = Some(zero - start);
+                assert_eq!(find_zero(&buf[start..]), expected);
+                assert_eq!(find_zero_word(&buf[start..]), expected);
+                #[cfg(target_arch = "x86_64")]
+                assert_eq!(find_zero_sse2(&buf[start..]), expected);
+
+                buf[zero] = 0x80;
+                if zero + 1 < buf.len() {
+                    buf[zero + 1] = 0x80;
+                }
+            }
+        }
+    }
+}